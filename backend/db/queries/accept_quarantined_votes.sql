@@ -0,0 +1,37 @@
+-- Releases quarantined votes matching the selector and adds them back to the counters.
+-- $1 collection, $2 set, $3 card and $4 format, all but the collection optional
+WITH accepted AS (
+    UPDATE votes
+    SET quarantined_at = NULL, reviewed_at = now()
+    WHERE quarantined_at IS NOT NULL
+        AND collection_id = $1
+        AND ($2::varchar IS NULL OR set_code = $2)
+        AND ($3::varchar IS NULL OR card_code = $3)
+        AND ($4::varchar IS NULL OR format_id = $4)
+    RETURNING collection_id, set_code, card_code, format_id, rating
+),
+counts AS (
+    SELECT collection_id, set_code, card_code, format_id,
+        count(*) AS votes,
+        count(*) FILTER (WHERE rating = 1) AS rated_1,
+        count(*) FILTER (WHERE rating = 2) AS rated_2,
+        count(*) FILTER (WHERE rating = 3) AS rated_3,
+        count(*) FILTER (WHERE rating = 4) AS rated_4,
+        count(*) FILTER (WHERE rating = 5) AS rated_5
+    FROM accepted
+    GROUP BY collection_id, set_code, card_code, format_id
+),
+updated AS (
+    UPDATE ratings r
+    SET rated_1 = r.rated_1 + c.rated_1,
+        rated_2 = r.rated_2 + c.rated_2,
+        rated_3 = r.rated_3 + c.rated_3,
+        rated_4 = r.rated_4 + c.rated_4,
+        rated_5 = r.rated_5 + c.rated_5
+    FROM counts c
+    WHERE r.collection_id = c.collection_id
+        AND r.set_code = c.set_code
+        AND r.card_code = c.card_code
+        AND r.format_id = c.format_id
+)
+SELECT coalesce(sum(votes), 0)::bigint FROM counts;
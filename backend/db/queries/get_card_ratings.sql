@@ -0,0 +1,12 @@
+SELECT collection_id,
+    set_code,
+    card_code,
+    format_id,
+    rated_1,
+    rated_2,
+    rated_3,
+    rated_4,
+    rated_5
+FROM ratings
+WHERE set_code = $1 AND card_code = $2
+ORDER BY collection_id, format_id;
@@ -0,0 +1,17 @@
+SELECT set_code,
+    card_code,
+    name,
+    rarity,
+    colors,
+    color_identity,
+    type_line,
+    mana_value,
+    layout,
+    image_uris
+FROM cards
+WHERE (set_code, card_code) IN (
+        SELECT set_code,
+            card_code
+        FROM ratings
+        WHERE collection_id = $1
+    );
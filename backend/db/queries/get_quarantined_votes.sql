@@ -0,0 +1,19 @@
+-- Quarantined votes per card, format and reason, largest groups first
+SELECT collection_id,
+    set_code,
+    card_code,
+    format_id,
+    quarantine_reason AS reason,
+    count(*) AS votes,
+    count(DISTINCT subnet_key) AS subnets,
+    count(*) FILTER (WHERE rating = 1) AS rated_1,
+    count(*) FILTER (WHERE rating = 2) AS rated_2,
+    count(*) FILTER (WHERE rating = 3) AS rated_3,
+    count(*) FILTER (WHERE rating = 4) AS rated_4,
+    count(*) FILTER (WHERE rating = 5) AS rated_5,
+    min(created_at) AS first_vote_at,
+    max(created_at) AS last_vote_at
+FROM votes
+WHERE quarantined_at IS NOT NULL AND ($1::varchar IS NULL OR collection_id = $1)
+GROUP BY collection_id, set_code, card_code, format_id, quarantine_reason
+ORDER BY votes DESC, collection_id, set_code, card_code, format_id;
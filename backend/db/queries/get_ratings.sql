@@ -1,11 +1,48 @@
-SELECT set_code,
-    card_code,
-    format_id,
-    rated_1,
-    rated_2,
-    rated_3,
-    rated_4,
-    rated_5
-FROM ratings
-WHERE collection_id = $1
-ORDER BY {set_order_stmt} length(card_code), card_code, format_id;
\ No newline at end of file
+SELECT r.set_code,
+    r.card_code,
+    r.format_id,
+    r.rated_1,
+    r.rated_2,
+    r.rated_3,
+    r.rated_4,
+    r.rated_5
+FROM ratings r
+    LEFT JOIN cards c ON c.set_code = r.set_code
+    AND c.card_code = r.card_code
+WHERE r.collection_id = $1
+    AND (
+        $2::text [] IS NULL
+        OR c.rarity = ANY($2)
+    )
+    AND (
+        $3::text [] IS NULL
+        OR (
+            CASE
+                WHEN cardinality($3) = 0 THEN cardinality(c.colors) = 0
+                ELSE c.colors @> $3
+            END
+        )
+    )
+    AND (
+        $4::text IS NULL
+        OR c.type_line ILIKE $4
+    )
+    AND (
+        $5::float8 IS NULL
+        OR c.mana_value >= $5
+    )
+    AND (
+        $6::float8 IS NULL
+        OR c.mana_value <= $6
+    )
+    AND (
+        $7::bigint IS NULL
+        OR r.rated_1::bigint + r.rated_2 + r.rated_3 + r.rated_4 + r.rated_5 >= $7
+    )
+    AND (
+        $9::text IS NULL
+        OR r.format_id = $9
+    )
+-- Sets missing from the order go last
+ORDER BY array_position($8::text [], r.set_code::text),
+    length(r.card_code), r.card_code, r.format_id;
@@ -0,0 +1,16 @@
+SELECT taken_at,
+    format_id,
+    sum(rated_1)::bigint AS rated_1,
+    sum(rated_2)::bigint AS rated_2,
+    sum(rated_3)::bigint AS rated_3,
+    sum(rated_4)::bigint AS rated_4,
+    sum(rated_5)::bigint AS rated_5
+FROM ratings_snapshots
+WHERE collection_id = $1
+    AND ($2::varchar IS NULL OR set_code = $2)
+    AND ($3::varchar IS NULL OR card_code = $3)
+    AND ($4::varchar IS NULL OR format_id = $4)
+    AND ($5::timestamptz IS NULL OR taken_at >= $5)
+    AND ($6::timestamptz IS NULL OR taken_at <= $6)
+GROUP BY taken_at, format_id
+ORDER BY taken_at, format_id;
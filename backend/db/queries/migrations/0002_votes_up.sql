@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS public.votes
+(
+    id bigserial PRIMARY KEY,
+    collection_id character varying(16) NOT NULL,
+    set_code character varying(16) NOT NULL,
+    card_code character varying(16) NOT NULL,
+    format_id character varying(16) NOT NULL,
+    rating smallint NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    voter_key character varying(64) NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT votes_ratings_fkey FOREIGN KEY (collection_id, set_code, card_code, format_id)
+        REFERENCES public.ratings (collection_id, set_code, card_code, format_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS votes_card_idx ON public.votes (collection_id, set_code, card_code, format_id);
+CREATE INDEX IF NOT EXISTS votes_created_at_idx ON public.votes (created_at);
+
+-- Votes cast before this table existed only survive as counters, so carry them over as anonymous events
+-- to keep rebuilding the aggregates lossless. Only runs while the table is still empty.
+INSERT INTO public.votes (collection_id, set_code, card_code, format_id, rating, voter_key, created_at)
+SELECT r.collection_id, r.set_code, r.card_code, r.format_id, b.rating, 'legacy', 'epoch'
+FROM public.ratings r
+CROSS JOIN LATERAL (VALUES (1, r.rated_1), (2, r.rated_2), (3, r.rated_3), (4, r.rated_4), (5, r.rated_5)) AS b(rating, n)
+CROSS JOIN LATERAL generate_series(1, b.n)
+WHERE NOT EXISTS (SELECT 1 FROM public.votes);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS public.collection_excluded_formats;
+DROP TABLE IF EXISTS public.collection_sets;
+DROP TABLE IF EXISTS public.collections;
+DROP TABLE IF EXISTS public.formats;
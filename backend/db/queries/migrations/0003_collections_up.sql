@@ -0,0 +1,33 @@
+CREATE TABLE IF NOT EXISTS public.formats
+(
+    id character varying(16) PRIMARY KEY,
+    enabled boolean NOT NULL DEFAULT false,
+    position integer NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS public.collections
+(
+    id character varying(16) PRIMARY KEY,
+    title text NOT NULL,
+    scryfall_query text NOT NULL,
+    releasing boolean NOT NULL DEFAULT false,
+    latest boolean NOT NULL DEFAULT false
+);
+
+-- At most one collection can be the one shown by default
+CREATE UNIQUE INDEX IF NOT EXISTS collections_latest_idx ON public.collections (latest) WHERE latest;
+
+CREATE TABLE IF NOT EXISTS public.collection_sets
+(
+    collection_id character varying(16) NOT NULL REFERENCES public.collections (id) ON DELETE CASCADE,
+    set_code character varying(16) NOT NULL,
+    position integer NOT NULL,
+    CONSTRAINT collection_sets_pkey PRIMARY KEY (collection_id, set_code)
+);
+
+CREATE TABLE IF NOT EXISTS public.collection_excluded_formats
+(
+    collection_id character varying(16) NOT NULL REFERENCES public.collections (id) ON DELETE CASCADE,
+    format_id character varying(16) NOT NULL REFERENCES public.formats (id) ON DELETE CASCADE,
+    CONSTRAINT collection_excluded_formats_pkey PRIMARY KEY (collection_id, format_id)
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS public.ratings_snapshots
+(
+    taken_at timestamp with time zone NOT NULL,
+    collection_id character varying(16) NOT NULL,
+    set_code character varying(16) NOT NULL,
+    card_code character varying(16) NOT NULL,
+    format_id character varying(16) NOT NULL,
+    rated_1 integer NOT NULL,
+    rated_2 integer NOT NULL,
+    rated_3 integer NOT NULL,
+    rated_4 integer NOT NULL,
+    rated_5 integer NOT NULL,
+    CONSTRAINT ratings_snapshots_pkey PRIMARY KEY (collection_id, set_code, card_code, format_id, taken_at)
+);
+
+CREATE INDEX IF NOT EXISTS ratings_snapshots_collection_idx ON public.ratings_snapshots (collection_id, taken_at);
@@ -0,0 +1,10 @@
+-- Local copy of scryfall card data, filled from bulk data files for offline collection resolution
+CREATE TABLE IF NOT EXISTS public.card_store
+(
+    set_code character varying(16) NOT NULL,
+    collector_number character varying(16) NOT NULL,
+    name text NOT NULL,
+    digital boolean NOT NULL DEFAULT false,
+    card jsonb NOT NULL,
+    CONSTRAINT card_store_pkey PRIMARY KEY (set_code, collector_number)
+);
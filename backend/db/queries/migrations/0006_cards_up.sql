@@ -0,0 +1,16 @@
+-- Card attributes captured while resolving collections, one row per printing
+CREATE TABLE IF NOT EXISTS public.cards
+(
+    set_code character varying(16) NOT NULL,
+    card_code character varying(16) NOT NULL,
+    name text NOT NULL,
+    rarity character varying(16) NOT NULL,
+    colors text[] NOT NULL,
+    color_identity text[] NOT NULL,
+    type_line text NOT NULL,
+    mana_value double precision NOT NULL,
+    layout character varying(32) NOT NULL,
+    image_uris jsonb,
+    updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT cards_pkey PRIMARY KEY (set_code, card_code)
+);
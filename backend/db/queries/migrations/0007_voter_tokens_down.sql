@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS public.votes_voter_id_idx;
+ALTER TABLE public.votes DROP COLUMN IF EXISTS voter_id;
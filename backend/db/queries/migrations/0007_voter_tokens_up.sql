@@ -0,0 +1,7 @@
+-- Votes cast with a voter token are that voter's current rating of the card, re-rating updates the row
+-- and retracting deletes it. Votes from before, or without a token, stay anonymous events.
+ALTER TABLE public.votes ADD COLUMN IF NOT EXISTS voter_id character varying(32);
+
+CREATE UNIQUE INDEX IF NOT EXISTS votes_voter_id_idx
+    ON public.votes (collection_id, set_code, card_code, format_id, voter_id)
+    WHERE voter_id IS NOT NULL;
@@ -0,0 +1,12 @@
+-- Token buckets of the rate limiter when `RATE_LIMIT_STORE=postgres`, shared by all backend replicas.
+-- Unlogged since losing them on a database crash only resets the limits.
+CREATE UNLOGGED TABLE IF NOT EXISTS public.rate_limit_buckets
+(
+    key text PRIMARY KEY,
+    tokens double precision NOT NULL,
+    -- Whether the last request found a token to take
+    allowed boolean NOT NULL,
+    updated_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS rate_limit_buckets_updated_at_idx ON public.rate_limit_buckets (updated_at);
@@ -0,0 +1,7 @@
+-- Quarantined votes would count again without the column, drop them like a purge would
+DELETE FROM public.votes WHERE quarantined_at IS NOT NULL;
+DROP INDEX IF EXISTS public.votes_quarantined_idx;
+ALTER TABLE public.votes DROP COLUMN IF EXISTS reviewed_at;
+ALTER TABLE public.votes DROP COLUMN IF EXISTS quarantine_reason;
+ALTER TABLE public.votes DROP COLUMN IF EXISTS quarantined_at;
+ALTER TABLE public.votes DROP COLUMN IF EXISTS subnet_key;
@@ -0,0 +1,9 @@
+-- Keyed hash of the voter's /24 (IPv4) or /64 (IPv6) network, to spot bursts from one subnet
+ALTER TABLE public.votes ADD COLUMN IF NOT EXISTS subnet_key character varying(64);
+-- Quarantined votes are left out of the `ratings` counters until an admin accepts or purges them
+ALTER TABLE public.votes ADD COLUMN IF NOT EXISTS quarantined_at timestamp with time zone;
+ALTER TABLE public.votes ADD COLUMN IF NOT EXISTS quarantine_reason character varying(32);
+-- Accepted votes are never flagged again
+ALTER TABLE public.votes ADD COLUMN IF NOT EXISTS reviewed_at timestamp with time zone;
+
+CREATE INDEX IF NOT EXISTS votes_quarantined_idx ON public.votes (collection_id) WHERE quarantined_at IS NOT NULL;
@@ -0,0 +1,10 @@
+-- Clients that may not vote, a single address is stored as a /32 or /128 network
+CREATE TABLE IF NOT EXISTS public.blocked_clients
+(
+    id bigserial PRIMARY KEY,
+    network cidr NOT NULL,
+    reason text NOT NULL DEFAULT '',
+    -- Blocks without an expiry last until they are removed
+    expires_at timestamp with time zone,
+    created_at timestamp with time zone NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS public.spent_challenges;
+ALTER TABLE public.collections DROP COLUMN IF EXISTS pow_difficulty;
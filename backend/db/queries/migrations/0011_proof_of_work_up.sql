@@ -0,0 +1,12 @@
+-- Leading zero bits a vote's proof of work needs, 0 lets votes through without a challenge
+ALTER TABLE public.collections ADD COLUMN IF NOT EXISTS pow_difficulty smallint NOT NULL DEFAULT 0;
+
+-- Nonces of challenges already used for a vote, kept until the challenge expires anyway.
+-- Unlogged since losing them on a database crash only allows replaying challenges for a few minutes.
+CREATE UNLOGGED TABLE IF NOT EXISTS public.spent_challenges
+(
+    nonce character varying(32) PRIMARY KEY,
+    expires_at timestamp with time zone NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS spent_challenges_expires_at_idx ON public.spent_challenges (expires_at);
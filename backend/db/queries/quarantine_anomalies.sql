@@ -0,0 +1,75 @@
+-- Quarantines recent votes that look coordinated and takes them out of the counters in one statement.
+-- $1 window in seconds, $2 identical votes per card and subnet, $3 identical votes per card within $4 seconds
+WITH recent AS (
+    SELECT id, collection_id, set_code, card_code, format_id, rating, subnet_key, created_at
+    FROM votes
+    WHERE created_at > now() - make_interval(secs => $1)
+        AND quarantined_at IS NULL
+        AND reviewed_at IS NULL
+),
+subnet_bursts AS (
+    SELECT v.id, 'subnet_burst' AS reason
+    FROM recent v
+    JOIN (
+        SELECT collection_id, set_code, card_code, format_id, rating, subnet_key
+        FROM recent
+        WHERE subnet_key IS NOT NULL
+        GROUP BY collection_id, set_code, card_code, format_id, rating, subnet_key
+        HAVING count(*) >= $2
+    ) g USING (collection_id, set_code, card_code, format_id, rating, subnet_key)
+),
+burst_starts AS (
+    SELECT collection_id, set_code, card_code, format_id, rating, created_at AS started_at
+    FROM (
+        SELECT collection_id, set_code, card_code, format_id, rating, created_at,
+            count(*) OVER (
+                PARTITION BY collection_id, set_code, card_code, format_id, rating
+                ORDER BY created_at
+                RANGE BETWEEN CURRENT ROW AND make_interval(secs => $4) FOLLOWING
+            ) AS n
+        FROM recent
+    ) x
+    WHERE n >= $3
+),
+time_bursts AS (
+    SELECT DISTINCT v.id, 'time_burst' AS reason
+    FROM recent v
+    JOIN burst_starts b USING (collection_id, set_code, card_code, format_id, rating)
+    WHERE v.created_at BETWEEN b.started_at AND b.started_at + make_interval(secs => $4)
+),
+flagged AS (
+    UPDATE votes v
+    SET quarantined_at = now(), quarantine_reason = f.reason
+    FROM (
+        SELECT DISTINCT ON (id) id, reason
+        FROM (SELECT * FROM subnet_bursts UNION ALL SELECT * FROM time_bursts) x
+        ORDER BY id, reason
+    ) f
+    WHERE v.id = f.id AND v.quarantined_at IS NULL
+    RETURNING v.collection_id, v.set_code, v.card_code, v.format_id, v.rating
+),
+counts AS (
+    SELECT collection_id, set_code, card_code, format_id,
+        count(*) AS votes,
+        count(*) FILTER (WHERE rating = 1) AS rated_1,
+        count(*) FILTER (WHERE rating = 2) AS rated_2,
+        count(*) FILTER (WHERE rating = 3) AS rated_3,
+        count(*) FILTER (WHERE rating = 4) AS rated_4,
+        count(*) FILTER (WHERE rating = 5) AS rated_5
+    FROM flagged
+    GROUP BY collection_id, set_code, card_code, format_id
+),
+updated AS (
+    UPDATE ratings r
+    SET rated_1 = r.rated_1 - c.rated_1,
+        rated_2 = r.rated_2 - c.rated_2,
+        rated_3 = r.rated_3 - c.rated_3,
+        rated_4 = r.rated_4 - c.rated_4,
+        rated_5 = r.rated_5 - c.rated_5
+    FROM counts c
+    WHERE r.collection_id = c.collection_id
+        AND r.set_code = c.set_code
+        AND r.card_code = c.card_code
+        AND r.format_id = c.format_id
+)
+SELECT coalesce(sum(votes), 0)::bigint FROM counts;
@@ -0,0 +1,27 @@
+WITH counts AS (
+    SELECT collection_id,
+        set_code,
+        card_code,
+        format_id,
+        count(*) FILTER (WHERE rating = 1) AS rated_1,
+        count(*) FILTER (WHERE rating = 2) AS rated_2,
+        count(*) FILTER (WHERE rating = 3) AS rated_3,
+        count(*) FILTER (WHERE rating = 4) AS rated_4,
+        count(*) FILTER (WHERE rating = 5) AS rated_5
+    FROM votes
+    WHERE ($1::varchar IS NULL OR collection_id = $1) AND quarantined_at IS NULL
+    GROUP BY collection_id, set_code, card_code, format_id
+)
+UPDATE ratings r
+SET rated_1 = coalesce(c.rated_1, 0),
+    rated_2 = coalesce(c.rated_2, 0),
+    rated_3 = coalesce(c.rated_3, 0),
+    rated_4 = coalesce(c.rated_4, 0),
+    rated_5 = coalesce(c.rated_5, 0)
+FROM ratings t
+LEFT JOIN counts c USING (collection_id, set_code, card_code, format_id)
+WHERE r.collection_id = t.collection_id
+    AND r.set_code = t.set_code
+    AND r.card_code = t.card_code
+    AND r.format_id = t.format_id
+    AND ($1::varchar IS NULL OR r.collection_id = $1);
@@ -0,0 +1,331 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+
+use crate::{
+    blocklist,
+    db::{
+        blocklist::{self as blocked_clients, SchemaBlockedClient},
+        collections, init_db,
+        quarantine::{self, QuarantineSelector, SchemaQuarantineGroup},
+    },
+    error::ApiError,
+    server::AppState,
+    signing,
+    util::{self, Collection, CollectionsJson},
+    validation::{CardCode, CollectionId, FormatId, SetCode, ValidJson, ValidPath, ValidQuery},
+    ServerData,
+};
+
+#[derive(Deserialize)]
+pub struct CreateCollectionRequest {
+    id: String,
+    #[serde(flatten)]
+    collection: Collection,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateCollectionRequest {
+    title: Option<String>,
+    scryfall_query: Option<String>,
+    set_order: Option<Vec<String>>,
+    releasing: Option<bool>,
+    excluded_formats: Option<Vec<String>>,
+    pow_difficulty: Option<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct SetLatestRequest {
+    collection_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateFormatRequest {
+    enabled: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuarantineExtractor {
+    collection_id: Option<CollectionId>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuarantineRequest {
+    collection_id: CollectionId,
+    set_code: Option<SetCode>,
+    card_code: Option<CardCode>,
+    format_id: Option<FormatId>,
+}
+
+impl From<QuarantineRequest> for QuarantineSelector {
+    fn from(request: QuarantineRequest) -> Self {
+        QuarantineSelector {
+            collection_id: request.collection_id.into_inner(),
+            set_code: request.set_code.map(SetCode::into_inner),
+            card_code: request.card_code.map(CardCode::into_inner),
+            format_id: request.format_id.map(FormatId::into_inner),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct QuarantineResponse {
+    votes: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlockClientRequest {
+    // A single address or a CIDR range
+    network: String,
+    #[serde(default)]
+    reason: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+// Admin routes are only reachable with `Authorization: Bearer <ADMIN_TOKEN>`, and not at all without a configured token
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+
+    match (state.admin_token.as_deref(), token) {
+        (Some(expected), Some(token))
+            if signing::constant_time_eq(expected.as_bytes(), token.as_bytes()) =>
+        {
+            Ok(next.run(request).await)
+        }
+        _ => Err(ApiError::Unauthorized),
+    }
+}
+
+async fn reload(state: &AppState) -> Result<Arc<ServerData>, ApiError> {
+    let server_data = Arc::new(ServerData {
+        collections: collections::load_collections(&state.pool).await?,
+    });
+    state.server_data.store(server_data.clone());
+
+    Ok(server_data)
+}
+
+// Registers the cards of a collection without blocking the request, scryfall pagination can take a while
+fn resolve_in_background(state: &AppState, collections: &CollectionsJson, collection_id: String) {
+    let (pool, source) = (state.pool.clone(), state.card_source.clone());
+    let collections = collections.clone();
+    tokio::spawn(async move {
+        match init_db::register_collections(
+            &pool,
+            source.as_ref(),
+            &collections,
+            std::slice::from_ref(&collection_id),
+        )
+        .await
+        {
+            Ok(()) => info!("Resolved collection {}", collection_id),
+            Err(e) => error!("Failed to resolve collection {}: {}", collection_id, e),
+        }
+    });
+}
+
+#[instrument(skip(state, request), fields(collection_id = request.id))]
+pub async fn create_collection(
+    State(state): State<AppState>,
+    ValidJson(request): ValidJson<CreateCollectionRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let _guard = collections::CATALOGUE_WRITE.lock().await;
+
+    let current = state.server_data.load_full();
+    if current.collections.entries.contains_key(&request.id) {
+        return Err(ApiError::CollectionExists(request.id));
+    }
+    util::validate_collection(
+        &request.id,
+        &request.collection,
+        &current.collections.formats,
+    )
+    .map_err(ApiError::InvalidCollection)?;
+
+    collections::create_collection(&state.pool, &request.id, &request.collection).await?;
+    let server_data = reload(&state).await?;
+    resolve_in_background(&state, &server_data.collections, request.id.clone());
+
+    Ok((StatusCode::CREATED, Json(request.collection)))
+}
+
+#[instrument(skip(state, request))]
+pub async fn update_collection(
+    State(state): State<AppState>,
+    ValidPath(collection_id): ValidPath<CollectionId>,
+    ValidJson(request): ValidJson<UpdateCollectionRequest>,
+) -> Result<Json<Collection>, ApiError> {
+    let _guard = collections::CATALOGUE_WRITE.lock().await;
+
+    let current = state.server_data.load_full();
+    let Some(old) = current.collections.entries.get(collection_id.as_str()) else {
+        return Err(ApiError::CollectionNotFound(collection_id.into_inner()));
+    };
+
+    let needs_resolve = request.scryfall_query.is_some()
+        || request.excluded_formats.is_some()
+        || request.releasing == Some(true);
+    let collection = Collection {
+        title: request.title.unwrap_or_else(|| old.title.clone()),
+        scryfall_query: request
+            .scryfall_query
+            .unwrap_or_else(|| old.scryfall_query.clone()),
+        set_order: request.set_order.unwrap_or_else(|| old.set_order.clone()),
+        releasing: request.releasing.unwrap_or(old.releasing),
+        excluded_formats: request
+            .excluded_formats
+            .unwrap_or_else(|| old.excluded_formats.clone()),
+        pow_difficulty: request.pow_difficulty.unwrap_or(old.pow_difficulty),
+    };
+    util::validate_collection(&collection_id, &collection, &current.collections.formats)
+        .map_err(ApiError::InvalidCollection)?;
+
+    collections::update_collection(&state.pool, &collection_id, &collection).await?;
+    let server_data = reload(&state).await?;
+    if needs_resolve {
+        resolve_in_background(&state, &server_data.collections, collection_id.into_inner());
+    }
+
+    Ok(Json(collection))
+}
+
+#[instrument(skip(state, request), fields(collection_id = request.collection_id))]
+pub async fn set_latest(
+    State(state): State<AppState>,
+    ValidJson(request): ValidJson<SetLatestRequest>,
+) -> Result<Json<CollectionsJson>, ApiError> {
+    let _guard = collections::CATALOGUE_WRITE.lock().await;
+
+    if !state
+        .server_data
+        .load()
+        .collections
+        .entries
+        .contains_key(&request.collection_id)
+    {
+        return Err(ApiError::UnknownCollection(request.collection_id));
+    }
+
+    collections::set_latest(&state.pool, &request.collection_id).await?;
+
+    Ok(Json(reload(&state).await?.collections.clone()))
+}
+
+#[instrument(skip(state, request))]
+pub async fn update_format(
+    State(state): State<AppState>,
+    ValidPath(format_id): ValidPath<FormatId>,
+    ValidJson(request): ValidJson<UpdateFormatRequest>,
+) -> Result<Json<CollectionsJson>, ApiError> {
+    let _guard = collections::CATALOGUE_WRITE.lock().await;
+
+    match collections::set_format_enabled(&state.pool, &format_id, request.enabled).await {
+        Ok(0) => Err(ApiError::FormatNotFound(format_id.into_inner())),
+        Ok(_) => Ok(Json(reload(&state).await?.collections.clone())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Quarantined votes grouped by card, format and the rule that flagged them
+#[instrument(skip(state))]
+pub async fn get_quarantine(
+    State(state): State<AppState>,
+    ValidQuery(QuarantineExtractor { collection_id }): ValidQuery<QuarantineExtractor>,
+) -> Result<Json<Vec<SchemaQuarantineGroup>>, ApiError> {
+    Ok(Json(
+        quarantine::get_quarantined_votes(
+            &state.pool,
+            collection_id.as_deref().map(String::as_str),
+        )
+        .await?,
+    ))
+}
+
+#[instrument(skip(state))]
+pub async fn accept_quarantine(
+    State(state): State<AppState>,
+    ValidJson(request): ValidJson<QuarantineRequest>,
+) -> Result<Json<QuarantineResponse>, ApiError> {
+    let votes = quarantine::accept_quarantined_votes(&state.pool, &request.into()).await?;
+    info!("Accepted {} quarantined votes", votes);
+
+    Ok(Json(QuarantineResponse { votes }))
+}
+
+#[instrument(skip(state))]
+pub async fn purge_quarantine(
+    State(state): State<AppState>,
+    ValidJson(request): ValidJson<QuarantineRequest>,
+) -> Result<Json<QuarantineResponse>, ApiError> {
+    let votes = quarantine::purge_quarantined_votes(&state.pool, &request.into()).await?;
+    info!("Purged {} quarantined votes", votes);
+
+    Ok(Json(QuarantineResponse {
+        votes: votes as i64,
+    }))
+}
+
+#[instrument(skip(state))]
+pub async fn get_blocked_clients(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SchemaBlockedClient>>, ApiError> {
+    Ok(Json(
+        blocked_clients::get_blocked_clients(&state.pool).await?,
+    ))
+}
+
+// Takes effect right away on this replica, other replicas pick it up on their next refresh
+#[instrument(skip(state))]
+pub async fn block_client(
+    State(state): State<AppState>,
+    ValidJson(request): ValidJson<BlockClientRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(network) = blocklist::parse_network(&request.network) else {
+        return Err(ApiError::InvalidRequest(format!(
+            "Invalid network '{}', expected an IP address or CIDR range",
+            request.network
+        )));
+    };
+
+    let client = blocked_clients::add_blocked_client(
+        &state.pool,
+        &network.to_string(),
+        &request.reason,
+        request.expires_at,
+    )
+    .await?;
+    blocklist::reload(&state).await?;
+    info!("Blocked {}", client.network);
+
+    Ok((StatusCode::CREATED, Json(client)))
+}
+
+#[instrument(skip(state))]
+pub async fn unblock_client(
+    State(state): State<AppState>,
+    ValidPath(id): ValidPath<i64>,
+) -> Result<StatusCode, ApiError> {
+    if !blocked_clients::delete_blocked_client(&state.pool, id).await? {
+        return Err(ApiError::BlockNotFound(id));
+    }
+    blocklist::reload(&state).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
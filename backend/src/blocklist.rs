@@ -0,0 +1,146 @@
+use std::{net::IpAddr, time::Duration};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_client_ip::SecureClientIp;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use tracing::{error, info, warn};
+
+use crate::{
+    db::blocklist::{self, SchemaBlockedClient},
+    error::ApiError,
+    net,
+    server::AppState,
+};
+
+// Parses a single address or a CIDR range, host bits of ranges are dropped so `10.0.0.1/8` means `10.0.0.0/8`
+pub fn parse_network(value: &str) -> Option<IpNet> {
+    let value = value.trim();
+    match value.parse::<IpNet>() {
+        Ok(x) => Some(x.trunc()),
+        Err(_) => value.parse::<IpAddr>().ok().map(IpNet::from),
+    }
+}
+
+// Snapshot of the active `blocked_clients`, swapped out as a whole on refresh
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    entries: Vec<(IpNet, Option<DateTime<Utc>>)>,
+}
+
+impl Blocklist {
+    pub fn new(clients: &[SchemaBlockedClient]) -> Self {
+        let entries = clients
+            .iter()
+            .filter_map(|x| match parse_network(&x.network) {
+                Some(network) => Some((network, x.expires_at)),
+                None => {
+                    warn!("Skipping unparseable blocked network '{}'", x.network);
+                    None
+                }
+            })
+            .collect();
+
+        Blocklist { entries }
+    }
+
+    // Entries expiring between two refreshes stop matching on time
+    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
+        let now = Utc::now();
+        let ip = net::canonical_ip(ip);
+        self.entries.iter().any(|(network, expires_at)| {
+            network.contains(&ip) && expires_at.is_none_or(|x| x > now)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+pub async fn reload(state: &AppState) -> Result<(), sqlx::Error> {
+    let clients = blocklist::get_active_blocked_clients(&state.pool).await?;
+    state.blocklist.store(Blocklist::new(&clients).into());
+
+    Ok(())
+}
+
+// Picks up entries added by other replicas or straight in the database
+pub fn spawn_refresh_job(state: AppState, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match reload(&state).await {
+                Ok(()) => info!("Loaded {} blocked networks", state.blocklist.load().len()),
+                Err(e) => error!("Failed to load blocked networks: {}", e),
+            }
+        }
+    });
+}
+
+pub async fn reject_blocked(
+    State(state): State<AppState>,
+    ip: SecureClientIp,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    match state.blocklist.load().is_blocked(&ip.0) {
+        true => Err(ApiError::Blocked),
+        false => Ok(next.run(request).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(network: &str, expires_at: Option<DateTime<Utc>>) -> SchemaBlockedClient {
+        SchemaBlockedClient {
+            id: 0,
+            network: network.into(),
+            reason: String::new(),
+            expires_at,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_parse_network() {
+        assert_eq!(parse_network("1.2.3.4"), "1.2.3.4/32".parse().ok());
+        assert_eq!(parse_network("10.1.2.3/8"), "10.0.0.0/8".parse().ok());
+        assert_eq!(parse_network("2001:db8::1"), "2001:db8::1/128".parse().ok());
+        assert_eq!(parse_network("1.2.3.4/33"), None);
+        assert_eq!(parse_network("nope"), None);
+    }
+
+    #[test]
+    fn test_is_blocked() {
+        let blocklist = Blocklist::new(&[
+            client("1.2.3.4/32", None),
+            client("10.0.0.0/8", Some(Utc::now() + chrono::Duration::hours(1))),
+            client(
+                "192.168.0.0/16",
+                Some(Utc::now() - chrono::Duration::hours(1)),
+            ),
+            client("2001:db8::/64", None),
+        ]);
+
+        let blocked = |x: &str| blocklist.is_blocked(&x.parse().unwrap());
+        assert!(blocked("1.2.3.4"));
+        assert!(!blocked("1.2.3.5"));
+        assert!(blocked("10.20.30.40"));
+        assert!(!blocked("192.168.1.1"));
+        assert!(blocked("2001:db8::42"));
+        assert!(!blocked("2001:db8:1::42"));
+        assert!(blocked("::ffff:1.2.3.4"));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use serde::{
+    de::{self, DeserializeSeed, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::{
+    card_query::{collector_number_value, CardQuery},
+    util::{CardDetail, Collection},
+};
+
+const BATCH_SIZE: usize = 1000;
+
+// One entry of a scryfall bulk data file, e.g. "Default Cards" from https://scryfall.com/docs/api/bulk-data
+#[derive(Deserialize)]
+struct BulkCard {
+    #[serde(flatten)]
+    detail: CardDetail,
+    #[serde(default)]
+    digital: bool,
+    #[serde(default)]
+    lang: Option<String>,
+}
+
+// Hands the cards of the top level array to the importer in batches, the file is never fully in memory
+struct BulkVisitor(mpsc::Sender<Vec<BulkCard>>);
+
+impl<'de> Visitor<'de> for BulkVisitor {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of scryfall cards")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while let Some(card) = seq.next_element::<BulkCard>()? {
+            // "All Cards" has one entry per language, the english one is the one we rate
+            if card.lang.as_deref().is_some_and(|x| x != "en") {
+                continue;
+            }
+            batch.push(card);
+            if batch.len() == BATCH_SIZE {
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                self.0
+                    .blocking_send(full)
+                    .map_err(|_| de::Error::custom("import aborted"))?;
+            }
+        }
+        if !batch.is_empty() {
+            self.0
+                .blocking_send(batch)
+                .map_err(|_| de::Error::custom("import aborted"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for BulkVisitor {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+fn parse_bulk_file(path: &Path, sender: mpsc::Sender<Vec<BulkCard>>) -> Result<(), Error> {
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(File::open(path)?));
+    BulkVisitor(sender).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+
+    Ok(())
+}
+
+// Returns the number of cards stored, malformed and duplicate printings are skipped
+async fn insert_batch(
+    tx: &mut Transaction<'_, Postgres>,
+    batch: Vec<BulkCard>,
+) -> Result<u64, Error> {
+    let batch = batch
+        .into_iter()
+        .filter(|x| {
+            (1..=16).contains(&x.detail.set.chars().count())
+                && (1..=16).contains(&x.detail.collector_number.chars().count())
+        })
+        .collect::<Vec<_>>();
+
+    let res = sqlx::query(
+        "INSERT INTO card_store (set_code, collector_number, name, digital, card)
+SELECT * FROM UNNEST($1::varchar[], $2::varchar[], $3::text[], $4::boolean[], $5::jsonb[])
+ON CONFLICT DO NOTHING",
+    )
+    .bind(
+        batch
+            .iter()
+            .map(|x| x.detail.set.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        batch
+            .iter()
+            .map(|x| x.detail.collector_number.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        batch
+            .iter()
+            .map(|x| x.detail.name.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(batch.iter().map(|x| x.digital).collect::<Vec<_>>())
+    .bind(
+        batch
+            .iter()
+            .map(|x| serde_json::to_value(&x.detail))
+            .collect::<Result<Vec<_>, _>>()?,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+// Replaces the card store with the contents of a bulk data file, a failed import keeps the previous store
+pub async fn import_bulk_file(pool: &PgPool, path: &Path) -> Result<u64, Error> {
+    info!("Importing scryfall bulk data from {}", path.display());
+
+    let (sender, mut receiver) = mpsc::channel::<Vec<BulkCard>>(4);
+    let owned_path = PathBuf::from(path);
+    let parser = tokio::task::spawn_blocking(move || parse_bulk_file(&owned_path, sender));
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM card_store")
+        .execute(&mut *tx)
+        .await?;
+    let mut imported = 0;
+    while let Some(batch) = receiver.recv().await {
+        imported += insert_batch(&mut tx, batch).await?;
+    }
+    parser
+        .await?
+        .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+    tx.commit().await?;
+
+    info!("Imported {} cards", imported);
+    Ok(imported)
+}
+
+// Evaluates the collection's scryfall query against the card store instead of the scryfall api
+pub async fn get_cards(pool: &PgPool, collection: &Collection) -> Result<Vec<CardDetail>, Error> {
+    let query = CardQuery::parse(&collection.scryfall_query)?;
+
+    let rows: Vec<(String, String, String, bool, serde_json::Value)> = match query.set_codes() {
+        Some(sets) => {
+            sqlx::query_as(
+                "SELECT set_code, collector_number, name, digital, card FROM card_store WHERE set_code = ANY($1)",
+            )
+            .bind(sets)
+            .fetch_all(pool)
+            .await?
+        }
+        None => {
+            sqlx::query_as("SELECT set_code, collector_number, name, digital, card FROM card_store")
+                .fetch_all(pool)
+                .await?
+        }
+    };
+
+    // Mirrors the `-is:digital` and `unique=cards` of the live search, keeping the first printing by `set_order`
+    let mut by_name = HashMap::<String, (usize, (u64, String), CardDetail)>::new();
+    for (set_code, collector_number, name, digital, card) in rows {
+        if digital || !query.matches(&set_code, &collector_number, digital) {
+            continue;
+        }
+
+        let set_position = collection
+            .set_order
+            .iter()
+            .position(|x| *x == set_code)
+            .unwrap_or(usize::MAX);
+        // Numeric part first, collector numbers without one sort last
+        let key = (
+            collector_number_value(&collector_number).unwrap_or(u64::MAX),
+            collector_number.clone(),
+        );
+        if let Some((position, number, _)) = by_name.get(&name) {
+            if (*position, number) <= (set_position, &key) {
+                continue;
+            }
+        }
+        let detail = serde_json::from_value::<CardDetail>(card).unwrap_or(CardDetail {
+            set: set_code,
+            collector_number,
+            ..Default::default()
+        });
+        by_name.insert(name, (set_position, key, detail));
+    }
+
+    // An empty result almost always means the bulk data was never imported
+    if by_name.is_empty() {
+        return Err(anyhow!(
+            "No card in the card store matches '{}', import a bulk data file first",
+            collection.scryfall_query
+        ));
+    }
+
+    Ok(by_name.into_values().map(|(_, _, x)| x).collect())
+}
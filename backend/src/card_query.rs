@@ -0,0 +1,308 @@
+use anyhow::{anyhow, Error};
+
+// Evaluates the subset of the scryfall search syntax our `scryfall_query` strings use against local card data
+// Supports `set:`/`s:`/`e:`/`edition:`, collector number comparisons via `cn`/`number`, `is:digital`,
+// `-` negation, `or`, implicit `and` and parentheses. Anything else is rejected rather than guessed.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Or(Vec<Expr>),
+    And(Vec<Expr>),
+    Not(Box<Expr>),
+    Set(String),
+    CollectorNumber(Comparison, String),
+    Digital,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CardQuery(Expr);
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Not,
+    Or,
+    Term(String),
+}
+
+// Longest operators first, so `>=` isn't read as `>`
+static OPERATORS: &[(&str, Comparison)] = &[
+    (">=", Comparison::Ge),
+    ("<=", Comparison::Le),
+    ("!=", Comparison::Ne),
+    ("≥", Comparison::Ge),
+    ("≤", Comparison::Le),
+    (":", Comparison::Eq),
+    ("=", Comparison::Eq),
+    (">", Comparison::Gt),
+    ("<", Comparison::Lt),
+];
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|x| x as u8)
+}
+
+// Queries are stored url encoded, e.g. `set%3Amh3+or+(e%3Aspg+cn≥39+cn≤58)`
+fn url_decode(query: &str) -> String {
+    let bytes = query.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2]))
+            {
+                (Some(a), Some(b)) => {
+                    decoded.push(a * 16 + b);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            x => decoded.push(x),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn tokenize(query: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for word in query.split_whitespace() {
+        let mut word = word;
+        while let Some(rest) = word.strip_prefix('(') {
+            tokens.push(Token::Open);
+            word = rest;
+        }
+        if let Some(rest) = word.strip_prefix('-') {
+            tokens.push(Token::Not);
+            word = rest;
+        }
+
+        let closing = word.len() - word.trim_end_matches(')').len();
+        let term = &word[..word.len() - closing];
+        if term.eq_ignore_ascii_case("or") {
+            tokens.push(Token::Or);
+        } else if !term.is_empty() {
+            tokens.push(Token::Term(term.to_lowercase()));
+        }
+        tokens.extend((0..closing).map(|_| Token::Close));
+    }
+
+    tokens
+}
+
+fn parse_term(term: &str) -> Result<Expr, Error> {
+    let (index, op, comparison) = term
+        .char_indices()
+        .find_map(|(i, _)| {
+            OPERATORS
+                .iter()
+                .find(|(op, _)| term[i..].starts_with(op))
+                .map(|(op, c)| (i, *op, *c))
+        })
+        .ok_or_else(|| anyhow!("Unsupported search term '{}'", term))?;
+    let (key, value) = (&term[..index], &term[index + op.len()..]);
+
+    match (key, comparison) {
+        ("set" | "s" | "e" | "edition", Comparison::Eq) => Ok(Expr::Set(value.to_owned())),
+        ("cn" | "number", c) => Ok(Expr::CollectorNumber(c, value.to_owned())),
+        ("is", Comparison::Eq) if value == "digital" => Ok(Expr::Digital),
+        _ => Err(anyhow!("Unsupported search term '{}'", term)),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.position += 1;
+        self.tokens.get(self.position - 1)
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut terms = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            terms.push(self.and()?);
+        }
+
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Expr::Or(terms)
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut terms = vec![];
+        while !matches!(self.peek(), None | Some(Token::Or) | Some(Token::Close)) {
+            terms.push(self.unary()?);
+        }
+
+        match terms.len() {
+            0 => Err(anyhow!("Empty search expression")),
+            1 => Ok(terms.remove(0)),
+            _ => Ok(Expr::And(terms)),
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(anyhow!("Unbalanced parentheses")),
+                }
+            }
+            Some(Token::Term(term)) => {
+                let term = term.clone();
+                parse_term(&term)
+            }
+            _ => Err(anyhow!("Unexpected end of search expression")),
+        }
+    }
+}
+
+// Leading digits of a collector number, `123★` and `123a` compare like `123`
+pub(crate) fn collector_number_value(collector_number: &str) -> Option<u64> {
+    let digits = collector_number
+        .chars()
+        .take_while(|x| x.is_ascii_digit())
+        .collect::<String>();
+    digits.parse::<u64>().ok()
+}
+
+fn compare(comparison: Comparison, collector_number: &str, value: &str) -> bool {
+    let ordering = match (
+        collector_number_value(collector_number),
+        collector_number_value(value),
+    ) {
+        (Some(a), Some(b)) if comparison != Comparison::Eq && comparison != Comparison::Ne => {
+            a.cmp(&b)
+        }
+        _ => collector_number.to_lowercase().as_str().cmp(value),
+    };
+
+    match comparison {
+        Comparison::Eq => ordering.is_eq(),
+        Comparison::Ne => ordering.is_ne(),
+        Comparison::Lt => ordering.is_lt(),
+        Comparison::Le => ordering.is_le(),
+        Comparison::Gt => ordering.is_gt(),
+        Comparison::Ge => ordering.is_ge(),
+    }
+}
+
+impl Expr {
+    fn matches(&self, set: &str, collector_number: &str, digital: bool) -> bool {
+        match self {
+            Expr::Or(x) => x.iter().any(|e| e.matches(set, collector_number, digital)),
+            Expr::And(x) => x.iter().all(|e| e.matches(set, collector_number, digital)),
+            Expr::Not(e) => !e.matches(set, collector_number, digital),
+            Expr::Set(x) => set.eq_ignore_ascii_case(x),
+            Expr::CollectorNumber(c, x) => compare(*c, collector_number, x),
+            Expr::Digital => digital,
+        }
+    }
+
+    fn set_codes(&self, codes: &mut Vec<String>) {
+        match self {
+            Expr::Or(x) | Expr::And(x) => x.iter().for_each(|e| e.set_codes(codes)),
+            Expr::Not(_) => (),
+            Expr::Set(x) => codes.push(x.clone()),
+            Expr::CollectorNumber(_, _) | Expr::Digital => (),
+        }
+    }
+
+    // Whether every match is guaranteed to be in one of the sets returned by `set_codes`
+    fn is_bounded_by_sets(&self) -> bool {
+        match self {
+            Expr::Or(x) => x.iter().all(Expr::is_bounded_by_sets),
+            Expr::And(x) => x.iter().any(Expr::is_bounded_by_sets),
+            Expr::Set(_) => true,
+            Expr::Not(_) | Expr::CollectorNumber(_, _) | Expr::Digital => false,
+        }
+    }
+}
+
+impl CardQuery {
+    pub fn parse(scryfall_query: &str) -> Result<CardQuery, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(&url_decode(scryfall_query)),
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(anyhow!("Unbalanced parentheses"));
+        }
+
+        Ok(CardQuery(expr))
+    }
+
+    pub fn matches(&self, set: &str, collector_number: &str, digital: bool) -> bool {
+        self.0.matches(set, collector_number, digital)
+    }
+
+    // Sets that can contain matches, `None` if the query may match cards of any set
+    pub fn set_codes(&self) -> Option<Vec<String>> {
+        if !self.0.is_bounded_by_sets() {
+            return None;
+        }
+
+        let mut codes = vec![];
+        self.0.set_codes(&mut codes);
+        codes.sort();
+        codes.dedup();
+        Some(codes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match() {
+        let query = CardQuery::parse("set%3Aotj+or+set%3Aotp+or+(e%3Aspg+cn≥29+cn≤38)").unwrap();
+        assert!(query.matches("otj", "1", false));
+        assert!(query.matches("OTP", "65", false));
+        assert!(query.matches("spg", "29", false));
+        assert!(query.matches("spg", "38★", false));
+        assert!(!query.matches("spg", "39", false));
+        assert!(!query.matches("mh3", "1", false));
+        assert_eq!(
+            query.set_codes(),
+            Some(vec!["otj".into(), "otp".into(), "spg".into()])
+        );
+
+        let query = CardQuery::parse("set%3Aneo+-is%3Adigital").unwrap();
+        assert!(query.matches("neo", "1", false));
+        assert!(!query.matches("neo", "1", true));
+
+        assert_eq!(CardQuery::parse("cn>5").unwrap().set_codes(), None);
+        assert!(CardQuery::parse("t%3Acreature").is_err());
+        assert!(CardQuery::parse("(set%3Aneo").is_err());
+        assert!(CardQuery::parse("set%3Aneo)").is_err());
+    }
+}
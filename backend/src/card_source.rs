@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::{
+    bulk,
+    scryfall::{ScryfallClient, ScryfallError},
+    util::{CardDetail, Collection},
+};
+
+// Resolves the cards of a collection, picked once at startup through `CARD_SOURCE`
+#[async_trait]
+pub trait CardSource: Send + Sync {
+    async fn get_cards(
+        &self,
+        collection_id: &str,
+        collection: &Collection,
+    ) -> Result<Vec<CardDetail>, Error>;
+}
+
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CardSourceKind {
+    #[default]
+    Scryfall,
+    // The local card store filled by `import-bulk`
+    Bulk,
+    // One JSON file per collection in `CARD_FIXTURES_PATH`
+    Fixtures,
+}
+
+pub struct ScryfallSource(pub ScryfallClient);
+
+#[async_trait]
+impl CardSource for ScryfallSource {
+    async fn get_cards(
+        &self,
+        collection_id: &str,
+        collection: &Collection,
+    ) -> Result<Vec<CardDetail>, Error> {
+        match self.0.search(&collection.scryfall_query).await {
+            Err(ScryfallError::NotFound(details)) => {
+                warn!(
+                    "No cards found for collection {}: {}",
+                    collection_id, details
+                );
+                Ok(vec![])
+            }
+            x => Ok(x?),
+        }
+    }
+}
+
+pub struct BulkSource(pub PgPool);
+
+#[async_trait]
+impl CardSource for BulkSource {
+    async fn get_cards(
+        &self,
+        _collection_id: &str,
+        collection: &Collection,
+    ) -> Result<Vec<CardDetail>, Error> {
+        bulk::get_cards(&self.0, collection).await
+    }
+}
+
+// Either a saved scryfall search response or a plain list of cards
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureFile {
+    List { data: Vec<CardDetail> },
+    Cards(Vec<CardDetail>),
+}
+
+// Reads `<dir>/<collection_id>.json`, for tests and deployments without access to scryfall
+pub struct FixtureSource(pub PathBuf);
+
+#[async_trait]
+impl CardSource for FixtureSource {
+    async fn get_cards(
+        &self,
+        collection_id: &str,
+        _collection: &Collection,
+    ) -> Result<Vec<CardDetail>, Error> {
+        let path = self.0.join(format!("{}.json", collection_id));
+        let content = tokio::fs::read(&path)
+            .await
+            .map_err(|e| anyhow!("Failed to read fixture {}: {}", path.display(), e))?;
+
+        Ok(match serde_json::from_slice::<FixtureFile>(&content)? {
+            FixtureFile::List { data } => data,
+            FixtureFile::Cards(x) => x,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fixture_source() {
+        let dir = std::env::temp_dir().join(format!("card-fixtures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("otj.json"),
+            r#"{"object": "list", "data": [{"set": "otj", "collector_number": "1", "rarity": "rare"}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("big.json"),
+            r#"[{"set": "big", "collector_number": "2"}]"#,
+        )
+        .unwrap();
+
+        let source = FixtureSource(dir.clone());
+        let cards = source
+            .get_cards("otj", &Collection::default())
+            .await
+            .unwrap();
+        assert_eq!(cards[0].rarity, "rare");
+        let cards = source
+            .get_cards("big", &Collection::default())
+            .await
+            .unwrap();
+        assert_eq!(cards[0].collector_number, "2");
+        assert!(source
+            .get_cards("mh3", &Collection::default())
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, PgPool};
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct SchemaBlockedClient {
+    pub id: i64,
+    // In CIDR notation, single addresses as `/32` or `/128`
+    pub network: String,
+    pub reason: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Includes expired entries, they stay around until removed so admins can see past blocks
+pub async fn get_blocked_clients(pool: &PgPool) -> Result<Vec<SchemaBlockedClient>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, network::text AS network, reason, expires_at, created_at
+    FROM blocked_clients
+    ORDER BY created_at DESC, id DESC",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_active_blocked_clients(
+    pool: &PgPool,
+) -> Result<Vec<SchemaBlockedClient>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, network::text AS network, reason, expires_at, created_at
+    FROM blocked_clients
+    WHERE expires_at IS NULL OR expires_at > now()",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn add_blocked_client(
+    pool: &PgPool,
+    network: &str,
+    reason: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<SchemaBlockedClient, sqlx::Error> {
+    sqlx::query_as(
+        "INSERT INTO blocked_clients (network, reason, expires_at)
+    VALUES ($1::cidr, $2, $3)
+    RETURNING id, network::text AS network, reason, expires_at, created_at",
+    )
+    .bind(network)
+    .bind(reason)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await
+}
+
+// Returns whether there was an entry with the id
+pub async fn delete_blocked_client(pool: &PgPool, id: i64) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM blocked_clients WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(res.rows_affected() > 0)
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+use sqlx::{prelude::FromRow, types::Json, PgPool};
+use std::collections::HashMap;
+
+use crate::util::CardDetail;
+
+static GET_COLLECTION_CARDS_QUERY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/db/queries/get_collection_cards.sql"
+));
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct SchemaCard {
+    #[serde(skip)]
+    pub set_code: String,
+    #[serde(skip)]
+    pub card_code: String,
+    pub name: String,
+    pub rarity: String,
+    pub colors: Vec<String>,
+    pub color_identity: Vec<String>,
+    pub type_line: String,
+    pub mana_value: f64,
+    pub layout: String,
+    pub image_uris: Option<Json<HashMap<String, String>>>,
+}
+
+// Stores the attributes of freshly resolved cards, placeholders without a name are skipped
+pub async fn upsert_cards(pool: &PgPool, cards: &[CardDetail]) -> Result<u64, sqlx::Error> {
+    let cards = cards
+        .iter()
+        .filter(|x| !x.name.is_empty())
+        .collect::<Vec<_>>();
+    if cards.is_empty() {
+        return Ok(0);
+    }
+
+    // Colors are sent comma separated, postgres can't unnest arrays of arrays into rows of arrays
+    let res = sqlx::query(
+        "INSERT INTO cards
+    (set_code, card_code, name, rarity, colors, color_identity, type_line, mana_value, layout, image_uris)
+SELECT set_code, card_code, name, rarity, string_to_array(colors, ','), string_to_array(color_identity, ','),
+    type_line, mana_value, layout, image_uris
+FROM UNNEST($1::varchar[], $2::varchar[], $3::text[], $4::varchar[], $5::text[], $6::text[], $7::text[],
+    $8::float8[], $9::varchar[], $10::jsonb[])
+    AS c(set_code, card_code, name, rarity, colors, color_identity, type_line, mana_value, layout, image_uris)
+ON CONFLICT (set_code, card_code) DO UPDATE SET
+    name = excluded.name,
+    rarity = excluded.rarity,
+    colors = excluded.colors,
+    color_identity = excluded.color_identity,
+    type_line = excluded.type_line,
+    mana_value = excluded.mana_value,
+    layout = excluded.layout,
+    image_uris = excluded.image_uris,
+    updated_at = now()",
+    )
+    .bind(cards.iter().map(|x| x.set.clone()).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.collector_number.clone()).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.name.clone()).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.rarity.clone()).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.colors.join(",")).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.color_identity.join(",")).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.type_line.clone()).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.cmc).collect::<Vec<_>>())
+    .bind(cards.iter().map(|x| x.layout.clone()).collect::<Vec<_>>())
+    .bind(
+        cards
+            .iter()
+            .map(|x| x.image_uris.clone().map(Json))
+            .collect::<Vec<_>>(),
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+// Attributes of every card rated in the collection, keyed by set and collector number
+pub async fn get_collection_cards(
+    pool: &PgPool,
+    collection_id: &str,
+) -> Result<HashMap<(String, String), SchemaCard>, sqlx::Error> {
+    Ok(sqlx::query_as::<_, SchemaCard>(GET_COLLECTION_CARDS_QUERY)
+        .bind(collection_id)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|x| ((x.set_code.clone(), x.card_code.clone()), x))
+        .collect())
+}
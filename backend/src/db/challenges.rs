@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+// Marks the nonce as used, returns false if it already was
+pub async fn spend_challenge(
+    tx: &mut Transaction<'_, Postgres>,
+    nonce: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "INSERT INTO spent_challenges (nonce, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(nonce)
+    .bind(expires_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+// Expired challenges are rejected before their nonce is looked up, so they can be forgotten
+pub async fn delete_expired_challenges(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM spent_challenges WHERE expires_at < now()")
+        .execute(pool)
+        .await?;
+
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+use sqlx::{prelude::FromRow, PgPool, Postgres, Transaction};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::util::{Collection, CollectionsJson, Format};
+
+// Serialises catalogue writes so the `ServerData` swapped in afterwards always reflects the latest write
+pub static CATALOGUE_WRITE: Mutex<()> = Mutex::const_new(());
+
+#[derive(Debug, FromRow)]
+struct SchemaFormat {
+    id: String,
+    enabled: bool,
+}
+
+#[derive(Debug, FromRow)]
+struct SchemaCollection {
+    id: String,
+    title: String,
+    scryfall_query: String,
+    releasing: bool,
+    latest: bool,
+    pow_difficulty: i16,
+}
+
+#[derive(Debug, FromRow)]
+struct SchemaCollectionEntry {
+    collection_id: String,
+    value: String,
+}
+
+async fn insert_formats(
+    tx: &mut Transaction<'_, Postgres>,
+    formats: &[Format],
+) -> Result<(), sqlx::Error> {
+    for (position, format) in formats.iter().enumerate() {
+        sqlx::query("INSERT INTO formats (id, enabled, position) VALUES ($1, $2, $3)")
+            .bind(&format.title)
+            .bind(format.enabled)
+            .bind(position as i32)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn insert_collection_entries(
+    tx: &mut Transaction<'_, Postgres>,
+    collection_id: &str,
+    collection: &Collection,
+) -> Result<(), sqlx::Error> {
+    for (position, set_code) in collection.set_order.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO collection_sets (collection_id, set_code, position) VALUES ($1, $2, $3)",
+        )
+        .bind(collection_id)
+        .bind(set_code)
+        .bind(position as i32)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    for format_id in collection.excluded_formats.iter() {
+        sqlx::query(
+            "INSERT INTO collection_excluded_formats (collection_id, format_id) VALUES ($1, $2)",
+        )
+        .bind(collection_id)
+        .bind(format_id)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn insert_collection(
+    tx: &mut Transaction<'_, Postgres>,
+    collection_id: &str,
+    collection: &Collection,
+    latest: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO collections (id, title, scryfall_query, releasing, latest, pow_difficulty)
+VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(collection_id)
+    .bind(&collection.title)
+    .bind(&collection.scryfall_query)
+    .bind(collection.releasing)
+    .bind(latest)
+    .bind(collection.pow_difficulty as i16)
+    .execute(&mut **tx)
+    .await?;
+
+    insert_collection_entries(tx, collection_id, collection).await
+}
+
+async fn insert_collections(
+    tx: &mut Transaction<'_, Postgres>,
+    collections: &CollectionsJson,
+) -> Result<(), sqlx::Error> {
+    insert_formats(tx, &collections.formats).await?;
+    for (collection_id, collection) in collections.entries.iter() {
+        insert_collection(
+            tx,
+            collection_id,
+            collection,
+            *collection_id == collections.latest,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+// Fills the catalogue tables from `collections` on first run, an already populated catalogue is left untouched
+pub async fn seed_collections(pool: &PgPool, collections: &CollectionsJson) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+
+    let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM collections")
+        .fetch_one(&mut *tx)
+        .await?;
+    if count > 0 {
+        return Ok(());
+    }
+
+    info!("Seeding {} collections", collections.entries.len());
+    insert_collections(&mut tx, collections).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// Replaces the whole catalogue, for when a collections file on disk is the source of truth
+// Cards already registered in `ratings` are kept
+pub async fn replace_collections(
+    pool: &PgPool,
+    collections: &CollectionsJson,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM collections")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM formats").execute(&mut *tx).await?;
+    insert_collections(&mut tx, collections).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn load_collections(pool: &PgPool) -> Result<CollectionsJson, Error> {
+    let formats =
+        sqlx::query_as::<_, SchemaFormat>("SELECT id, enabled FROM formats ORDER BY position")
+            .fetch_all(pool)
+            .await?;
+    let collections = sqlx::query_as::<_, SchemaCollection>(
+        "SELECT id, title, scryfall_query, releasing, latest, pow_difficulty FROM collections",
+    )
+    .fetch_all(pool)
+    .await?;
+    let sets = sqlx::query_as::<_, SchemaCollectionEntry>(
+        "SELECT collection_id, set_code AS value FROM collection_sets ORDER BY collection_id, position",
+    )
+    .fetch_all(pool)
+    .await?;
+    let excluded_formats = sqlx::query_as::<_, SchemaCollectionEntry>(
+        "SELECT e.collection_id, e.format_id AS value
+FROM collection_excluded_formats e
+JOIN formats f ON f.id = e.format_id
+ORDER BY e.collection_id, f.position",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let latest = collections
+        .iter()
+        .find(|c| c.latest)
+        .map(|c| c.id.clone())
+        .unwrap_or_default();
+
+    let mut entries = collections
+        .into_iter()
+        .map(|c| {
+            (
+                c.id,
+                Collection {
+                    title: c.title,
+                    scryfall_query: c.scryfall_query,
+                    releasing: c.releasing,
+                    pow_difficulty: c.pow_difficulty as u8,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    for set in sets {
+        if let Some(c) = entries.get_mut(&set.collection_id) {
+            c.set_order.push(set.value);
+        }
+    }
+    for format in excluded_formats {
+        if let Some(c) = entries.get_mut(&format.collection_id) {
+            c.excluded_formats.push(format.value);
+        }
+    }
+
+    Ok(CollectionsJson {
+        latest,
+        formats: formats
+            .into_iter()
+            .map(|f| Format {
+                title: f.id,
+                enabled: f.enabled,
+            })
+            .collect(),
+        entries,
+    })
+}
+
+pub async fn create_collection(
+    pool: &PgPool,
+    collection_id: &str,
+    collection: &Collection,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    insert_collection(&mut tx, collection_id, collection, false).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// Overwrites every field of an existing collection, `latest` is left as is
+pub async fn update_collection(
+    pool: &PgPool,
+    collection_id: &str,
+    collection: &Collection,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "UPDATE collections SET title = $2, scryfall_query = $3, releasing = $4, pow_difficulty = $5
+WHERE id = $1",
+    )
+    .bind(collection_id)
+    .bind(&collection.title)
+    .bind(&collection.scryfall_query)
+    .bind(collection.releasing)
+    .bind(collection.pow_difficulty as i16)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM collection_sets WHERE collection_id = $1")
+        .bind(collection_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM collection_excluded_formats WHERE collection_id = $1")
+        .bind(collection_id)
+        .execute(&mut *tx)
+        .await?;
+    insert_collection_entries(&mut tx, collection_id, collection).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn set_latest(pool: &PgPool, collection_id: &str) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+
+    // Two statements as the unique index on `latest` is checked per row
+    sqlx::query("UPDATE collections SET latest = false WHERE latest")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE collections SET latest = true WHERE id = $1")
+        .bind(collection_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn set_format_enabled(
+    pool: &PgPool,
+    format_id: &str,
+    enabled: bool,
+) -> Result<u64, Error> {
+    let res = sqlx::query("UPDATE formats SET enabled = $2 WHERE id = $1")
+        .bind(format_id)
+        .bind(enabled)
+        .execute(pool)
+        .await?;
+
+    Ok(res.rows_affected())
+}
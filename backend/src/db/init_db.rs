@@ -2,62 +2,41 @@
 
 use std::collections::{HashMap, HashSet};
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 
 use sqlx::{prelude::FromRow, PgPool};
-use tracing::info;
+use tracing::{error, info};
 
 use crate::{
-    util::{self, Collection, CollectionItem, CollectionsJson, Format},
+    card_source::CardSource,
+    db::cards,
+    util::{Collection, CollectionItem, CollectionsJson, Format},
     ServerData,
 };
 
-static MIGRATIONS: &[&str] = &[include_str!(concat!(
-    env!("CARGO_MANIFEST_DIR"),
-    "/db/queries/migrations/0001_ratings_up.sql"
-))];
-
-async fn generate_ratings_query(
-    formats: &Vec<Format>,
-    collection_item: &CollectionItem,
-) -> Result<String, anyhow::Error> {
-    let (name, ref cards) = collection_item;
-
-    Ok(formats
-        .iter()
-        .map(|format| {
-            cards
-                .iter()
-                .map(|x| {
-                    format!(
-                        "('{}', '{}', '{}', '{}')",
-                        name.replace("'", "''"),
-                        x.set.replace("'", "''"),
-                        x.collector_number.replace("'", "''"),
-                        format.title,
-                    )
-                })
-                .collect::<Vec<String>>()
-                .join(",")
-        })
-        .collect::<Vec<String>>()
-        .join(","))
-}
-
+// Registers every card in every given format, cards and formats are sent as arrays so the statement size doesn't grow with the collection
 pub async fn run_ratings_query(
     pool: &PgPool,
-    formats: &Vec<Format>,
+    formats: &[Format],
     collection_item: &CollectionItem,
 ) -> Result<(), anyhow::Error> {
-    let ratings_query: String = generate_ratings_query(&formats, &collection_item).await?;
+    let (name, ref cards) = collection_item;
     sqlx::query(
-        format!(
-            "INSERT INTO ratings(collection_id, set_code, card_code, format_id)
-VALUES {} ON CONFLICT DO NOTHING",
-            ratings_query
-        )
-        .as_str(),
+        "INSERT INTO ratings(collection_id, set_code, card_code, format_id)
+SELECT $1, c.set_code, c.card_code, f.format_id
+FROM UNNEST($2::varchar[], $3::varchar[]) AS c(set_code, card_code)
+    CROSS JOIN UNNEST($4::varchar[]) AS f(format_id)
+ON CONFLICT DO NOTHING",
     )
+    .bind(name)
+    .bind(cards.iter().map(|x| x.set.clone()).collect::<Vec<_>>())
+    .bind(
+        cards
+            .iter()
+            .map(|x| x.collector_number.clone())
+            .collect::<Vec<_>>(),
+    )
+    .bind(formats.iter().map(|x| x.title.clone()).collect::<Vec<_>>())
     .execute(pool)
     .await?;
 
@@ -66,10 +45,20 @@ VALUES {} ON CONFLICT DO NOTHING",
 
 async fn register_supported_sets(
     pool: &PgPool,
+    source: &dyn CardSource,
     collections: &CollectionsJson,
 ) -> Result<(), Error> {
+    // One unreachable or broken query shouldn't keep the other collections from being registered
+    let mut failed = vec![];
     for (key, collection) in collections.entries.iter() {
-        let item = util::resolve_collection(key, collection).await?;
+        let item = match source.get_cards(key, collection).await {
+            Ok(x) => (key.clone(), x),
+            Err(e) => {
+                error!("Failed to resolve collection {}: {}", key, e);
+                failed.push(key.clone());
+                continue;
+            }
+        };
         run_ratings_query(
             pool,
             &collections
@@ -81,11 +70,38 @@ async fn register_supported_sets(
             &item,
         )
         .await?;
+        cards::upsert_cards(pool, &item.1).await?;
     }
 
+    if !failed.is_empty() {
+        return Err(anyhow!("Failed to resolve collections {:?}", failed));
+    }
     Ok(())
 }
 
+// Registers the cards of the given collections only, e.g. after they were added at runtime
+pub async fn register_collections(
+    pool: &PgPool,
+    source: &dyn CardSource,
+    collections: &CollectionsJson,
+    collection_ids: &[String],
+) -> Result<(), Error> {
+    register_supported_sets(
+        pool,
+        source,
+        &CollectionsJson {
+            entries: collections
+                .entries
+                .iter()
+                .filter(|(id, _)| collection_ids.contains(id))
+                .map(|(a, b)| (a.to_owned(), b.to_owned()))
+                .collect(),
+            ..collections.clone()
+        },
+    )
+    .await
+}
+
 #[derive(Debug, FromRow, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct SchemaCards {
     collection_id: String,
@@ -93,13 +109,13 @@ pub struct SchemaCards {
 }
 
 fn misses_format(
-    collection_id: &String,
+    collection_id: &str,
     formats: &Vec<Format>,
     known_pairs: &HashSet<SchemaCards>,
 ) -> bool {
     for format in formats {
         if !known_pairs.contains(&SchemaCards {
-            collection_id: collection_id.clone(),
+            collection_id: collection_id.to_owned(),
             format_id: format.title.clone(),
         }) {
             return true;
@@ -109,13 +125,12 @@ fn misses_format(
     false
 }
 
-pub async fn init_db(pool: &PgPool, server_data: &ServerData) -> Result<(), Error> {
-    for migration in MIGRATIONS {
-        info!(migration);
-        sqlx::query(migration).execute(pool).await?;
-    }
-
-    // @TODO(ckolb): This should pull from a dedicated "collections" table once we have one
+pub async fn init_db(
+    pool: &PgPool,
+    source: &dyn CardSource,
+    server_data: &ServerData,
+) -> Result<(), Error> {
+    // Collection/format pairs that already have their cards registered, the catalogue itself lives in the `collections` tables
     let known_sets =
         sqlx::query_as::<_, SchemaCards>("SELECT DISTINCT collection_id, format_id FROM ratings")
             .fetch_all(pool)
@@ -147,14 +162,19 @@ pub async fn init_db(pool: &PgPool, server_data: &ServerData) -> Result<(), Erro
         .map(|(a, b)| (a.to_owned(), b.to_owned()))
         .collect::<HashMap<String, Collection>>();
 
-    register_supported_sets(
+    // Missing collections are retried on the next start, until then they have no cards to rate
+    if let Err(e) = register_supported_sets(
         pool,
+        source,
         &CollectionsJson {
             entries: filtered_collections,
             ..server_data.collections.clone()
         },
     )
-    .await?;
+    .await
+    {
+        error!("{}", e);
+    }
 
     Ok(())
 }
@@ -1,11 +1,21 @@
 use serde::Serialize;
-use sqlx::{prelude::FromRow, Pool, Postgres};
+use sqlx::{postgres::PgArguments, prelude::FromRow, query::QueryAs, Pool, Postgres, Transaction};
 
-static GET_RATINGS_QUERY: &str = &include_str!(concat!(
+static GET_RATINGS_QUERY: &str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/db/queries/get_ratings.sql"
 ));
 
+static GET_CARD_RATINGS_QUERY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/db/queries/get_card_ratings.sql"
+));
+
+static REBUILD_RATINGS_QUERY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/db/queries/rebuild_ratings.sql"
+));
+
 #[derive(Debug)]
 pub enum RatingsValue {
     Rated1,
@@ -25,34 +35,251 @@ impl RatingsValue {
             RatingsValue::Rated5 => "rated_5",
         }
     }
+
+    pub fn from_score(score: i16) -> Option<Self> {
+        match score {
+            1 => Some(RatingsValue::Rated1),
+            2 => Some(RatingsValue::Rated2),
+            3 => Some(RatingsValue::Rated3),
+            4 => Some(RatingsValue::Rated4),
+            5 => Some(RatingsValue::Rated5),
+            _ => None,
+        }
+    }
+
+    pub fn to_score(&self) -> i16 {
+        match self {
+            RatingsValue::Rated1 => 1,
+            RatingsValue::Rated2 => 2,
+            RatingsValue::Rated3 => 3,
+            RatingsValue::Rated4 => 4,
+            RatingsValue::Rated5 => 5,
+        }
+    }
 }
 
-#[tracing::instrument]
-pub async fn increment_rating(
-    pool: &Pool<Postgres>,
-    rating: &RatingsValue,
-    collection_id: &String,
-    card_code: &String,
-    set_code: &String,
-    format_id: &String,
+// Who cast a vote, as stored with it
+#[derive(Debug)]
+pub struct Voter {
+    // Keyed hashes of the address and its subnet, see `signing`
+    pub key: String,
+    pub subnet_key: String,
+    // From the voter token, votes without one can't be changed later
+    pub id: Option<String>,
+}
+
+async fn lock_ratings_row(
+    tx: &mut Transaction<'_, Postgres>,
+    collection_id: &str,
+    card_code: &str,
+    set_code: &str,
+    format_id: &str,
 ) -> Result<u64, sqlx::Error> {
     let res = sqlx::query(
+        "SELECT 1 FROM ratings
+    WHERE collection_id = $1 AND card_code = $2 AND set_code = $3 AND format_id = $4
+    FOR UPDATE",
+    )
+    .bind(collection_id)
+    .bind(card_code)
+    .bind(set_code)
+    .bind(format_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+// Adds `delta` to the counter of `rating`
+async fn add_to_counter(
+    tx: &mut Transaction<'_, Postgres>,
+    rating: &RatingsValue,
+    delta: i32,
+    collection_id: &str,
+    card_code: &str,
+    set_code: &str,
+    format_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
         format!(
             "UPDATE ratings
-    SET {0} = {0} + 1
-    WHERE collection_id = $2 AND card_code = $3 AND set_code = $4 AND format_id = $5",
+    SET {0} = {0} + $5
+    WHERE collection_id = $1 AND card_code = $2 AND set_code = $3 AND format_id = $4",
             rating.to_sql_column()
         )
         .as_str(),
     )
-    .bind(rating.to_sql_column())
     .bind(collection_id)
     .bind(card_code)
     .bind(set_code)
     .bind(format_id)
-    .execute(pool)
+    .bind(delta)
+    .execute(&mut **tx)
     .await?;
 
+    Ok(())
+}
+
+// Bumps the aggregate counter and records the individual vote in the same transaction,
+// so `votes` can always be used to rebuild `ratings`.
+// With a voter id a repeated vote replaces the voter's earlier rating of the card instead of adding to it.
+// Returns the number of matching `ratings` rows, 0 for cards that are not part of the collection.
+// Runs in the caller's transaction, which has to commit it.
+#[tracing::instrument(skip(tx))]
+pub async fn increment_rating(
+    tx: &mut Transaction<'_, Postgres>,
+    rating: &RatingsValue,
+    collection_id: &String,
+    card_code: &String,
+    set_code: &String,
+    format_id: &String,
+    voter: &Voter,
+) -> Result<u64, sqlx::Error> {
+    // Locking the counters row also serializes concurrent votes of the same voter
+    let rows = lock_ratings_row(tx, collection_id, card_code, set_code, format_id).await?;
+    if rows == 0 {
+        return Ok(0);
+    }
+
+    let previous = match &voter.id {
+        Some(voter_id) => {
+            sqlx::query_as::<_, (i16, bool)>(
+                "SELECT rating, quarantined_at IS NOT NULL FROM votes
+    WHERE collection_id = $1 AND set_code = $2 AND card_code = $3 AND format_id = $4 AND voter_id = $5",
+            )
+            .bind(collection_id)
+            .bind(set_code)
+            .bind(card_code)
+            .bind(format_id)
+            .bind(voter_id)
+            .fetch_optional(&mut **tx)
+            .await?
+        }
+        None => None,
+    };
+
+    match previous {
+        Some((score, _)) if score == rating.to_score() => (),
+        Some((score, quarantined)) => {
+            // A quarantined vote stays quarantined, it isn't part of the counters either way
+            if let (false, Some(previous)) = (quarantined, RatingsValue::from_score(score)) {
+                add_to_counter(
+                    tx,
+                    &previous,
+                    -1,
+                    collection_id,
+                    card_code,
+                    set_code,
+                    format_id,
+                )
+                .await?;
+                add_to_counter(tx, rating, 1, collection_id, card_code, set_code, format_id)
+                    .await?;
+            }
+            sqlx::query(
+                "UPDATE votes
+    SET rating = $6, voter_key = $7, subnet_key = $8, created_at = now()
+    WHERE collection_id = $1 AND set_code = $2 AND card_code = $3 AND format_id = $4 AND voter_id = $5",
+            )
+            .bind(collection_id)
+            .bind(set_code)
+            .bind(card_code)
+            .bind(format_id)
+            .bind(&voter.id)
+            .bind(rating.to_score())
+            .bind(&voter.key)
+            .bind(&voter.subnet_key)
+            .execute(&mut **tx)
+            .await?;
+        }
+        None => {
+            add_to_counter(tx, rating, 1, collection_id, card_code, set_code, format_id).await?;
+            sqlx::query(
+                "INSERT INTO votes (collection_id, set_code, card_code, format_id, rating, voter_key, voter_id, subnet_key)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            )
+            .bind(collection_id)
+            .bind(set_code)
+            .bind(card_code)
+            .bind(format_id)
+            .bind(rating.to_score())
+            .bind(&voter.key)
+            .bind(&voter.id)
+            .bind(&voter.subnet_key)
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(rows)
+}
+
+// Takes back the vote of `voter_id` on a card, `false` if there was none
+#[tracing::instrument]
+pub async fn retract_rating(
+    pool: &Pool<Postgres>,
+    collection_id: &str,
+    card_code: &str,
+    set_code: &str,
+    format_id: &str,
+    voter_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    lock_ratings_row(&mut tx, collection_id, card_code, set_code, format_id).await?;
+    let previous = sqlx::query_as::<_, (i16, bool)>(
+        "DELETE FROM votes
+    WHERE collection_id = $1 AND set_code = $2 AND card_code = $3 AND format_id = $4 AND voter_id = $5
+    RETURNING rating, quarantined_at IS NOT NULL",
+    )
+    .bind(collection_id)
+    .bind(set_code)
+    .bind(card_code)
+    .bind(format_id)
+    .bind(voter_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((score, quarantined)) = previous else {
+        return Ok(false);
+    };
+    if let (false, Some(previous)) = (quarantined, RatingsValue::from_score(score)) {
+        add_to_counter(
+            &mut tx,
+            &previous,
+            -1,
+            collection_id,
+            card_code,
+            set_code,
+            format_id,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+// Recomputes the `rated_*` counters from the `votes` events, either for a single collection or everything.
+// Deleting votes (e.g. a spam wave) and running this afterwards rolls their effect back.
+pub async fn rebuild_ratings(
+    pool: &Pool<Postgres>,
+    collection_id: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Keep votes from landing between counting and writing the totals
+    sqlx::query("LOCK TABLE votes IN SHARE MODE")
+        .execute(&mut *tx)
+        .await?;
+    let res = sqlx::query(REBUILD_RATINGS_QUERY)
+        .bind(collection_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
     Ok(res.rows_affected())
 }
 
@@ -68,50 +295,92 @@ pub struct SchemaRatings {
     rated_5: i32,
 }
 
-/* This function builds a sql statement like
+#[derive(Debug, FromRow)]
+pub struct SchemaCardRatings {
+    pub collection_id: String,
+    #[sqlx(flatten)]
+    pub ratings: SchemaRatings,
+}
 
-(
-    case set_code
-    when 'a' then 0
-    when 'b' then 1
-    end
-),
+impl SchemaRatings {
+    pub fn counts(&self) -> [i64; 5] {
+        [
+            self.rated_1.into(),
+            self.rated_2.into(),
+            self.rated_3.into(),
+            self.rated_4.into(),
+            self.rated_5.into(),
+        ]
+    }
+}
 
-dynamically for a list e.g. ["a", "b"]
+// Narrows down the cards of `get_ratings`, `None` leaves an attribute unfiltered
+#[derive(Debug, Default)]
+pub struct RatingsFilter {
+    pub rarities: Option<Vec<String>>,
+    // Cards having at least these colors, an empty list matches colorless cards only
+    pub colors: Option<Vec<String>>,
+    // Substring of the type line, case insensitive
+    pub type_line: Option<String>,
+    pub mana_value_min: Option<f64>,
+    pub mana_value_max: Option<f64>,
+    // Per format, rows with fewer votes are left out
+    pub min_votes: Option<i64>,
+    // Rows of this format only
+    pub format_id: Option<String>,
+}
 
-note the trailing comma is imporant as other statements will follow, hence the empty return for the empty list
-*/
-fn make_set_order_by_expr(set_order: &Vec<String>) -> String {
-    if set_order.len() == 0 {
-        return String::new();
-    }
+// All ratings of the collection, ordered by `set_order` and collector number
+pub fn get_ratings_query<'q>(
+    collection_id: &'q str,
+    set_order: &'q [String],
+    filter: &'q RatingsFilter,
+) -> QueryAs<'q, Postgres, SchemaRatings, PgArguments> {
+    let type_pattern = filter.type_line.as_ref().map(|x| {
+        format!(
+            "%{}%",
+            x.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        )
+    });
 
-    let wheres = set_order
-        .iter()
-        .enumerate()
-        .map(|(i, x)| format!(" when '{}' then {} ", x, i))
-        .collect::<String>();
-    return format!("( case set_code {} end),", wheres);
+    sqlx::query_as::<_, SchemaRatings>(GET_RATINGS_QUERY)
+        .bind(collection_id)
+        .bind(&filter.rarities)
+        .bind(&filter.colors)
+        .bind(type_pattern)
+        .bind(filter.mana_value_min)
+        .bind(filter.mana_value_max)
+        .bind(filter.min_votes)
+        .bind(set_order)
+        .bind(&filter.format_id)
 }
 
 pub async fn get_ratings(
     pool: &Pool<Postgres>,
-    collection_id: &String,
-    set_order: &Vec<String>,
+    collection_id: &str,
+    set_order: &[String],
+    filter: &RatingsFilter,
 ) -> Result<Vec<SchemaRatings>, anyhow::Error> {
-    // Note that this ordering is currently not depended upon by the frontend
-    // Mostly here for historical reasons if I run into the problem again
-    let results = sqlx::query_as::<_, SchemaRatings>(
-        GET_RATINGS_QUERY
-            .replace(
-                "{set_order_stmt}",
-                make_set_order_by_expr(set_order).as_str(),
-            )
-            .as_str(),
-    )
-    .bind(collection_id)
-    .fetch_all(pool)
-    .await?;
+    let results = get_ratings_query(collection_id, set_order, filter)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(results)
+}
+
+// Every collection and format a single printing is rated in
+pub async fn get_card_ratings(
+    pool: &Pool<Postgres>,
+    set_code: &str,
+    card_code: &str,
+) -> Result<Vec<SchemaCardRatings>, anyhow::Error> {
+    let results = sqlx::query_as::<_, SchemaCardRatings>(GET_CARD_RATINGS_QUERY)
+        .bind(set_code)
+        .bind(card_code)
+        .fetch_all(pool)
+        .await?;
 
     Ok(results)
 }
@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Error};
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, prelude::FromRow, Connection, Executor, PgPool, Postgres};
+use tracing::info;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+impl Migration {
+    // Only the up script is checksummed, it's the one that shaped the live schema
+    fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.up.as_bytes()))
+    }
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/db/queries/migrations/",
+                $name,
+                "_up.sql"
+            )),
+            down: include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/db/queries/migrations/",
+                $name,
+                "_down.sql"
+            )),
+        }
+    };
+}
+
+// Append only, never edit a migration that has been deployed. The checksum check refuses to start otherwise.
+pub static MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_ratings"),
+    migration!(2, "0002_votes"),
+    migration!(3, "0003_collections"),
+    migration!(4, "0004_ratings_snapshots"),
+    migration!(5, "0005_card_store"),
+    migration!(6, "0006_cards"),
+    migration!(7, "0007_voter_tokens"),
+    migration!(8, "0008_rate_limits"),
+    migration!(9, "0009_vote_quarantine"),
+    migration!(10, "0010_blocked_clients"),
+    migration!(11, "0011_proof_of_work"),
+];
+
+// Arbitrary key for `pg_advisory_lock`, keeps concurrently starting servers from migrating at the same time
+const MIGRATION_LOCK_KEY: i64 = 0x6d74_6752_6174_6572;
+
+#[derive(Debug, FromRow)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+async fn lock(pool: &PgPool) -> Result<PoolConnection<Postgres>, Error> {
+    let mut conn = pool.acquire().await?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS public.schema_migrations
+(
+    version bigint PRIMARY KEY,
+    name text NOT NULL,
+    checksum character(64) NOT NULL,
+    applied_at timestamp with time zone NOT NULL DEFAULT now()
+)",
+    )
+    .await?;
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(conn)
+}
+
+async fn unlock(mut conn: PoolConnection<Postgres>) -> Result<(), Error> {
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+async fn applied(conn: &mut PoolConnection<Postgres>) -> Result<Vec<AppliedMigration>, Error> {
+    Ok(sqlx::query_as::<_, AppliedMigration>(
+        "SELECT version, name, checksum, applied_at::text AS applied_at FROM schema_migrations ORDER BY version",
+    )
+    .fetch_all(&mut **conn)
+    .await?)
+}
+
+// Refuses to continue if the database ran migrations this binary doesn't know or knows differently
+fn verify(applied: &[AppliedMigration]) -> Result<(), Error> {
+    for a in applied {
+        match MIGRATIONS.iter().find(|m| m.version == a.version) {
+            None => {
+                return Err(anyhow!(
+                    "Database has unknown migration {} ({}) applied, is this binary outdated?",
+                    a.version,
+                    a.name
+                ))
+            }
+            Some(m) if m.checksum() != a.checksum => {
+                return Err(anyhow!(
+                    "Checksum drift for migration {} ({}): applied {}, expected {}",
+                    a.version,
+                    a.name,
+                    a.checksum,
+                    m.checksum()
+                ))
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn check_target(target: i64) -> Result<(), Error> {
+    if target != 0 && MIGRATIONS.iter().all(|m| m.version != target) {
+        return Err(anyhow!("Unknown migration version {}", target));
+    }
+
+    Ok(())
+}
+
+async fn run_up(conn: &mut PoolConnection<Postgres>, target: Option<i64>) -> Result<(), Error> {
+    let applied = applied(conn).await?;
+    verify(&applied)?;
+
+    let target = target.unwrap_or(i64::MAX);
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| m.version <= target && applied.iter().all(|a| a.version != m.version))
+    {
+        info!(
+            "Applying migration {} ({})",
+            migration.version, migration.name
+        );
+
+        let mut tx = conn.begin().await?;
+        // Executing the raw string uses the simple query protocol, which allows several statements per file
+        tx.execute(migration.up).await?;
+        sqlx::query("INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(migration.checksum())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+async fn run_down(conn: &mut PoolConnection<Postgres>, target: i64) -> Result<(), Error> {
+    let applied = applied(conn).await?;
+    verify(&applied)?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .rev()
+        .filter(|m| m.version > target && applied.iter().any(|a| a.version == m.version))
+    {
+        info!(
+            "Reverting migration {} ({})",
+            migration.version, migration.name
+        );
+
+        let mut tx = conn.begin().await?;
+        tx.execute(migration.down).await?;
+        sqlx::query("DELETE FROM schema_migrations WHERE version = $1")
+            .bind(migration.version)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+// Applies all pending migrations up to and including `target`, or all of them for `None`
+pub async fn migrate_up(pool: &PgPool, target: Option<i64>) -> Result<(), Error> {
+    if let Some(t) = target {
+        check_target(t)?;
+    }
+
+    let mut conn = lock(pool).await?;
+    let res = run_up(&mut conn, target).await;
+    unlock(conn).await?;
+
+    res
+}
+
+// Reverts applied migrations until `target` is the latest one, 0 reverts everything
+pub async fn migrate_down(pool: &PgPool, target: i64) -> Result<(), Error> {
+    check_target(target)?;
+
+    let mut conn = lock(pool).await?;
+    let res = run_down(&mut conn, target).await;
+    unlock(conn).await?;
+
+    res
+}
+
+pub async fn status(pool: &PgPool) -> Result<Vec<AppliedMigration>, Error> {
+    let mut conn = lock(pool).await?;
+    let res = applied(&mut conn).await;
+    unlock(conn).await?;
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+        assert!(MIGRATIONS
+            .iter()
+            .all(|m| m.name.starts_with(&format!("{:04}_", m.version))));
+    }
+}
@@ -1,2 +1,10 @@
+pub mod blocklist;
+pub mod cards;
+pub mod challenges;
+pub mod collections;
 pub mod init_db;
 pub mod lib;
+pub mod migrations;
+pub mod quarantine;
+pub mod rate_limits;
+pub mod snapshots;
@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, PgPool};
+use tracing::{error, info, warn};
+
+static QUARANTINE_ANOMALIES_QUERY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/db/queries/quarantine_anomalies.sql"
+));
+
+static ACCEPT_QUARANTINED_VOTES_QUERY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/db/queries/accept_quarantined_votes.sql"
+));
+
+static GET_QUARANTINED_VOTES_QUERY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/db/queries/get_quarantined_votes.sql"
+));
+
+// Thresholds of the anomaly scan, see `quarantine_anomalies.sql`
+#[derive(Debug, Clone, Copy)]
+pub struct AnomalyThresholds {
+    pub window_secs: f64,
+    pub subnet_votes: i64,
+    pub burst_votes: i64,
+    pub burst_secs: f64,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct SchemaQuarantineGroup {
+    pub collection_id: String,
+    pub set_code: String,
+    pub card_code: String,
+    pub format_id: String,
+    pub reason: Option<String>,
+    pub votes: i64,
+    pub subnets: i64,
+    rated_1: i64,
+    rated_2: i64,
+    rated_3: i64,
+    rated_4: i64,
+    rated_5: i64,
+    pub first_vote_at: DateTime<Utc>,
+    pub last_vote_at: DateTime<Utc>,
+}
+
+// Quarantined votes of a collection, optionally narrowed down to a set, card and format
+#[derive(Debug)]
+pub struct QuarantineSelector {
+    pub collection_id: String,
+    pub set_code: Option<String>,
+    pub card_code: Option<String>,
+    pub format_id: Option<String>,
+}
+
+// Returns the number of newly quarantined votes
+pub async fn quarantine_anomalies(
+    pool: &PgPool,
+    thresholds: &AnomalyThresholds,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(QUARANTINE_ANOMALIES_QUERY)
+        .bind(thresholds.window_secs)
+        .bind(thresholds.subnet_votes)
+        .bind(thresholds.burst_votes)
+        .bind(thresholds.burst_secs)
+        .fetch_one(pool)
+        .await
+}
+
+// Scans every `period`, overlapping windows are fine since flagged votes aren't looked at again
+pub fn spawn_anomaly_job(pool: PgPool, period: Duration, thresholds: AnomalyThresholds) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match quarantine_anomalies(&pool, &thresholds).await {
+                Ok(0) => info!("Found no vote anomalies"),
+                Ok(n) => warn!("Quarantined {} votes, review them at /admin/quarantine", n),
+                Err(e) => error!("Failed to scan votes for anomalies: {}", e),
+            }
+        }
+    });
+}
+
+pub async fn get_quarantined_votes(
+    pool: &PgPool,
+    collection_id: Option<&str>,
+) -> Result<Vec<SchemaQuarantineGroup>, sqlx::Error> {
+    sqlx::query_as(GET_QUARANTINED_VOTES_QUERY)
+        .bind(collection_id)
+        .fetch_all(pool)
+        .await
+}
+
+// Counts the votes again and keeps future scans from flagging them, returns the number of votes
+pub async fn accept_quarantined_votes(
+    pool: &PgPool,
+    selector: &QuarantineSelector,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(ACCEPT_QUARANTINED_VOTES_QUERY)
+        .bind(&selector.collection_id)
+        .bind(&selector.set_code)
+        .bind(&selector.card_code)
+        .bind(&selector.format_id)
+        .fetch_one(pool)
+        .await
+}
+
+// Quarantined votes are already left out of the counters, so they can just be dropped
+pub async fn purge_quarantined_votes(
+    pool: &PgPool,
+    selector: &QuarantineSelector,
+) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query(
+        "DELETE FROM votes
+    WHERE quarantined_at IS NOT NULL
+        AND collection_id = $1
+        AND ($2::varchar IS NULL OR set_code = $2)
+        AND ($3::varchar IS NULL OR card_code = $3)
+        AND ($4::varchar IS NULL OR format_id = $4)",
+    )
+    .bind(&selector.collection_id)
+    .bind(&selector.set_code)
+    .bind(&selector.card_code)
+    .bind(&selector.format_id)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,41 @@
+use sqlx::PgPool;
+
+// Refills the bucket for the time since its last use, then takes a token if there is a whole one.
+// Returns the tokens left and whether one was taken. New buckets start full, `capacity` is at least 1.
+pub async fn take_token(
+    pool: &PgPool,
+    key: &str,
+    capacity: f64,
+    refill_per_second: f64,
+) -> Result<(f64, bool), sqlx::Error> {
+    sqlx::query_as(
+        "INSERT INTO rate_limit_buckets AS b (key, tokens, allowed, updated_at)
+VALUES ($1, $2::float8 - 1, true, clock_timestamp())
+ON CONFLICT (key) DO UPDATE SET
+    tokens = CASE
+        WHEN least($2::float8, b.tokens + extract(epoch FROM clock_timestamp() - b.updated_at)::float8 * $3::float8) >= 1
+        THEN least($2::float8, b.tokens + extract(epoch FROM clock_timestamp() - b.updated_at)::float8 * $3::float8) - 1
+        ELSE least($2::float8, b.tokens + extract(epoch FROM clock_timestamp() - b.updated_at)::float8 * $3::float8)
+    END,
+    allowed = least($2::float8, b.tokens + extract(epoch FROM clock_timestamp() - b.updated_at)::float8 * $3::float8) >= 1,
+    updated_at = clock_timestamp()
+RETURNING tokens, allowed",
+    )
+    .bind(key)
+    .bind(capacity)
+    .bind(refill_per_second)
+    .fetch_one(pool)
+    .await
+}
+
+// Buckets untouched for longer than it takes to refill them are full again, dropping them changes nothing
+pub async fn delete_idle_buckets(pool: &PgPool, idle_seconds: f64) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query(
+        "DELETE FROM rate_limit_buckets WHERE updated_at < now() - make_interval(secs => $1)",
+    )
+    .bind(idle_seconds)
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, PgPool};
+use tracing::{error, info};
+
+static GET_RATINGS_HISTORY_QUERY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/db/queries/get_ratings_history.sql"
+));
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct SchemaSnapshot {
+    pub taken_at: DateTime<Utc>,
+    pub format_id: String,
+    rated_1: i64,
+    rated_2: i64,
+    rated_3: i64,
+    rated_4: i64,
+    rated_5: i64,
+}
+
+impl SchemaSnapshot {
+    pub fn counts(&self) -> [i64; 5] {
+        [
+            self.rated_1,
+            self.rated_2,
+            self.rated_3,
+            self.rated_4,
+            self.rated_5,
+        ]
+    }
+}
+
+// Copies the current totals of every card with at least one vote
+pub async fn take_snapshot(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query(
+        "INSERT INTO ratings_snapshots
+    (taken_at, collection_id, set_code, card_code, format_id, rated_1, rated_2, rated_3, rated_4, rated_5)
+SELECT now(), collection_id, set_code, card_code, format_id, rated_1, rated_2, rated_3, rated_4, rated_5
+FROM ratings
+WHERE rated_1 + rated_2 + rated_3 + rated_4 + rated_5 > 0",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+// Time since the last snapshot by the database's own clock, `None` before the first one
+async fn since_last_snapshot(pool: &PgPool) -> Result<Option<Duration>, sqlx::Error> {
+    let (seconds,): (Option<f64>,) = sqlx::query_as(
+        "SELECT extract(epoch FROM now() - max(taken_at))::float8 FROM ratings_snapshots",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(seconds.map(|x| Duration::from_secs_f64(x.max(0.0))))
+}
+
+// Snapshots every `period`. After a restart the first one waits until a period has passed since the last one
+pub fn spawn_snapshot_job(pool: PgPool, period: Duration) {
+    tokio::spawn(async move {
+        let delay = match since_last_snapshot(&pool).await {
+            Ok(x) => x.map_or(Duration::ZERO, |x| period.saturating_sub(x)),
+            Err(e) => {
+                error!("Failed to read last snapshot: {}", e);
+                Duration::ZERO
+            }
+        };
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + delay, period);
+        loop {
+            interval.tick().await;
+            match take_snapshot(&pool).await {
+                Ok(n) => info!("Snapshotted {} ratings rows", n),
+                Err(e) => error!("Failed to snapshot ratings: {}", e),
+            }
+        }
+    });
+}
+
+// Totals per snapshot and format, summed over the whole collection unless narrowed down to a single card
+pub async fn get_ratings_history(
+    pool: &PgPool,
+    collection_id: &str,
+    card: Option<(&str, &str)>,
+    format_id: Option<&str>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<SchemaSnapshot>, anyhow::Error> {
+    let results = sqlx::query_as::<_, SchemaSnapshot>(GET_RATINGS_HISTORY_QUERY)
+        .bind(collection_id)
+        .bind(card.map(|x| x.0))
+        .bind(card.map(|x| x.1))
+        .bind(format_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(results)
+}
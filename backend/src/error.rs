@@ -0,0 +1,236 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// Every error the api returns, rendered as `{"code": ..., "message": ..., "details": ...}`
+// The codes are part of the api, clients match on them rather than on the message
+#[derive(Debug)]
+pub enum ApiError {
+    // Malformed query string, path or body
+    InvalidRequest(String),
+    // Well formed JSON body of the wrong shape
+    InvalidBody(String),
+    InvalidRating(String),
+    // A collection or format referenced by a query parameter or body does not exist
+    UnknownCollection(String),
+    UnknownFormat(String),
+    ExcludedFormat(String),
+    SetNotInCollection(String),
+    // The resource addressed by the path does not exist
+    CollectionNotFound(String),
+    FormatNotFound(String),
+    CardNotFound(String, String),
+    // The caller has no vote on the card to retract
+    VoteNotFound(String, String),
+    BlockNotFound(i64),
+    CollectionExists(String),
+    InvalidCollection(String),
+    Unauthorized,
+    // The client's address is on the blocklist
+    Blocked,
+    // The vote needs a solved challenge of this difficulty from `GET /challenge`
+    ChallengeRequired(u8),
+    InvalidChallenge(String),
+    RateLimited,
+    // Logged with `id`, clients only get the id to quote in bug reports
+    Internal { id: String, error: anyhow::Error },
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
+
+// Unique enough to find the log line, without telling clients anything about the server
+fn error_id() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    format!(
+        "{:x}-{:04x}",
+        chrono::Utc::now().timestamp_millis(),
+        COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+    )
+}
+
+impl ApiError {
+    pub fn internal(error: impl Into<anyhow::Error>) -> Self {
+        ApiError::Internal {
+            id: error_id(),
+            error: error.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidBody(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::CollectionNotFound(_)
+            | ApiError::FormatNotFound(_)
+            | ApiError::CardNotFound(_, _)
+            | ApiError::VoteNotFound(_, _)
+            | ApiError::BlockNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::CollectionExists(_) => StatusCode::CONFLICT,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Blocked | ApiError::ChallengeRequired(_) | ApiError::InvalidChallenge(_) => {
+                StatusCode::FORBIDDEN
+            }
+            ApiError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::InvalidRating(_) => "invalid_rating",
+            ApiError::UnknownCollection(_) => "unknown_collection",
+            ApiError::UnknownFormat(_) => "unknown_format",
+            ApiError::ExcludedFormat(_) => "excluded_format",
+            ApiError::SetNotInCollection(_) => "set_not_in_collection",
+            ApiError::CollectionNotFound(_) => "collection_not_found",
+            ApiError::FormatNotFound(_) => "format_not_found",
+            ApiError::CardNotFound(_, _) => "card_not_found",
+            ApiError::VoteNotFound(_, _) => "vote_not_found",
+            ApiError::BlockNotFound(_) => "block_not_found",
+            ApiError::CollectionExists(_) => "collection_exists",
+            ApiError::InvalidCollection(_) => "invalid_collection",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Blocked => "blocked",
+            ApiError::ChallengeRequired(_) => "challenge_required",
+            ApiError::InvalidChallenge(_) => "invalid_challenge",
+            ApiError::RateLimited => "rate_limited",
+            ApiError::Internal { .. } => "internal_error",
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::UnknownCollection(x) | ApiError::CollectionNotFound(x) => {
+                Some(json!({ "collection_id": x }))
+            }
+            ApiError::CollectionExists(x) => Some(json!({ "collection_id": x })),
+            ApiError::UnknownFormat(x)
+            | ApiError::ExcludedFormat(x)
+            | ApiError::FormatNotFound(x) => Some(json!({ "format_id": x })),
+            ApiError::SetNotInCollection(x) => Some(json!({ "set_code": x })),
+            ApiError::CardNotFound(set_code, card_code)
+            | ApiError::VoteNotFound(set_code, card_code) => {
+                Some(json!({ "set_code": set_code, "card_code": card_code }))
+            }
+            ApiError::InvalidRating(x) => Some(json!({ "rating": x })),
+            ApiError::BlockNotFound(x) => Some(json!({ "id": x })),
+            ApiError::ChallengeRequired(x) => Some(json!({ "difficulty": x })),
+            ApiError::Internal { id, .. } => Some(json!({ "error_id": id })),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidRequest(x)
+            | ApiError::InvalidBody(x)
+            | ApiError::InvalidChallenge(x) => write!(f, "{}", x),
+            ApiError::InvalidRating(_) => write!(f, "Rating must be between 1 and 5"),
+            ApiError::UnknownCollection(_) | ApiError::CollectionNotFound(_) => {
+                write!(f, "Unknown collection")
+            }
+            ApiError::UnknownFormat(_) | ApiError::FormatNotFound(_) => write!(f, "Unknown format"),
+            ApiError::ExcludedFormat(_) => write!(f, "Format is excluded from this collection"),
+            ApiError::SetNotInCollection(_) => write!(f, "Set not in collection"),
+            ApiError::CardNotFound(_, _) => write!(f, "Unknown card"),
+            ApiError::VoteNotFound(_, _) => write!(f, "No vote to retract"),
+            ApiError::BlockNotFound(_) => write!(f, "Unknown blocklist entry"),
+            ApiError::CollectionExists(_) => write!(f, "Collection already exists"),
+            ApiError::InvalidCollection(x) => write!(f, "Invalid collection: {}", x),
+            ApiError::Unauthorized => write!(f, "Invalid admin token"),
+            ApiError::Blocked => write!(f, "Voting is blocked for this network"),
+            ApiError::ChallengeRequired(_) => write!(f, "Votes need a solved challenge"),
+            ApiError::RateLimited => write!(
+                f,
+                "Please report if you saw this error during intended usage of the website."
+            ),
+            ApiError::Internal { .. } => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        ApiError::internal(error)
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        ApiError::internal(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal { id, error } = &self {
+            tracing::error!(error_id = id, "{:#}", error);
+        }
+
+        (
+            self.status(),
+            Json(ErrorBody {
+                code: self.code(),
+                message: self.to_string(),
+                details: self.details(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(error: ApiError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_error_body() {
+        let (status, x) = body(ApiError::UnknownCollection("nope".into())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            x,
+            json!({
+                "code": "unknown_collection",
+                "message": "Unknown collection",
+                "details": {"collection_id": "nope"},
+            })
+        );
+
+        let (status, x) = body(ApiError::internal(anyhow::anyhow!(
+            "relation \"ratings\" does not exist"
+        )))
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(x["code"], "internal_error");
+        assert!(!x.to_string().contains("ratings"));
+        assert!(x["details"]["error_id"].as_str().is_some());
+    }
+}
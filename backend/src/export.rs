@@ -0,0 +1,145 @@
+use std::io;
+
+use axum::body::Body;
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::error;
+
+use crate::{
+    db::lib::{self, RatingsFilter, SchemaRatings},
+    stats::RatingStats,
+};
+
+// Rows in flight between the database and the client, the rest of the collection stays in postgres
+const BUFFERED_ROWS: usize = 64;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    collection_id: &'a str,
+    #[serde(flatten)]
+    ratings: &'a SchemaRatings,
+    votes: i64,
+    mean: Option<f64>,
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn encode(format: ExportFormat, row: &ExportRow) -> Result<String, serde_json::Error> {
+    Ok(match format {
+        ExportFormat::Csv => {
+            let counts = row.ratings.counts();
+            format!(
+                "{},{},{},{},{},{},{},{},{},{},{}\n",
+                csv_field(row.collection_id),
+                csv_field(&row.ratings.set_code),
+                csv_field(&row.ratings.card_code),
+                csv_field(&row.ratings.format_id),
+                counts[0],
+                counts[1],
+                counts[2],
+                counts[3],
+                counts[4],
+                row.votes,
+                row.mean.map(|x| x.to_string()).unwrap_or_default()
+            )
+        }
+        ExportFormat::Jsonl => serde_json::to_string(row)? + "\n",
+    })
+}
+
+// Streams one line per card and format straight from the database cursor into the response body
+pub fn stream_ratings(
+    pool: PgPool,
+    collection_id: String,
+    set_order: Vec<String>,
+    format: ExportFormat,
+) -> Body {
+    let (tx, rx) = mpsc::channel::<Result<String, io::Error>>(BUFFERED_ROWS);
+
+    tokio::spawn(async move {
+        if let ExportFormat::Csv = format {
+            let header = "collection_id,set_code,card_code,format_id,rated_1,rated_2,rated_3,rated_4,rated_5,votes,mean\n";
+            if tx.send(Ok(header.to_owned())).await.is_err() {
+                return;
+            }
+        }
+
+        let filter = RatingsFilter::default();
+        let mut rows = lib::get_ratings_query(&collection_id, &set_order, &filter).fetch(&pool);
+        loop {
+            let line = match rows.try_next().await {
+                Ok(None) => return,
+                Ok(Some(ratings)) => {
+                    let stats = RatingStats::from_counts(ratings.counts());
+                    encode(
+                        format,
+                        &ExportRow {
+                            collection_id: &collection_id,
+                            ratings: &ratings,
+                            votes: stats.votes,
+                            mean: stats.mean,
+                        },
+                    )
+                    .map_err(io::Error::other)
+                }
+                Err(e) => {
+                    error!("Export of {} failed: {}", collection_id, e);
+                    Err(io::Error::other(e))
+                }
+            };
+
+            let failed = line.is_err();
+            // The client hung up, stop reading from the database
+            if tx.send(line).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    Body::from_stream(ReceiverStream::new(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("123"), "123");
+        assert_eq!(csv_field("1,2"), "\"1,2\"");
+        assert_eq!(csv_field("a\"b"), "\"a\"\"b\"");
+    }
+}
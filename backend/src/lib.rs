@@ -0,0 +1,95 @@
+use axum::{
+    middleware,
+    routing::{delete, get, patch, post, put},
+    Router,
+};
+use axum_client_ip::SecureClientIpSource;
+use server::AppState;
+use util::CollectionsJson;
+
+pub mod admin;
+pub mod blocklist;
+pub mod bulk;
+pub mod card_query;
+pub mod card_source;
+pub mod db;
+pub mod error;
+pub mod export;
+pub mod net;
+pub mod pow;
+pub mod rate_limit;
+pub mod reload;
+pub mod scryfall;
+pub mod server;
+pub mod signing;
+pub mod stats;
+pub mod summary;
+pub mod util;
+pub mod validation;
+
+#[derive(Clone, Debug)]
+pub struct ServerData {
+    pub collections: CollectionsJson,
+}
+
+impl std::fmt::Debug for AppState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.server_data.fmt(f)
+    }
+}
+
+// All routes of the api, shared by the binary and the integration tests
+pub fn router(app_state: AppState, ip_source: SecureClientIpSource) -> Router {
+    let admin_routes = Router::new()
+        .route("/collections", post(admin::create_collection))
+        .route(
+            "/collections/:collection_id",
+            patch(admin::update_collection),
+        )
+        .route("/latest", put(admin::set_latest))
+        .route("/formats/:format_id", patch(admin::update_format))
+        .route("/quarantine", get(admin::get_quarantine))
+        .route("/quarantine/accept", post(admin::accept_quarantine))
+        .route("/quarantine/purge", post(admin::purge_quarantine))
+        .route(
+            "/blocked",
+            get(admin::get_blocked_clients).post(admin::block_client),
+        )
+        .route("/blocked/:id", delete(admin::unblock_client))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            admin::require_admin,
+        ));
+    let read_routes = Router::new()
+        .route("/ratings", get(server::get_ratings))
+        .route("/ratings/history", get(server::get_ratings_history))
+        .route("/ratings/summary", get(server::get_ratings_summary))
+        .route("/ratings/export", get(server::export_ratings))
+        .route("/collections", get(server::get_collections))
+        .route("/challenge", get(server::get_challenge))
+        .route("/cards/:set_code/:card_code", get(server::get_card_ratings))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            rate_limit::limit_reads,
+        ));
+    let vote_routes = Router::new()
+        .route(
+            "/ratings",
+            post(server::post_ratings).delete(server::delete_ratings),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            rate_limit::limit_votes,
+        ))
+        // Outermost, so blocked clients don't use up rate limit tokens either
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            blocklist::reject_blocked,
+        ));
+    Router::new()
+        .merge(read_routes)
+        .merge(vote_routes)
+        .nest("/admin", admin_routes)
+        .layer(ip_source.into_extension())
+        .with_state(app_state)
+}
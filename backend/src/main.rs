@@ -1,37 +1,230 @@
 use std::{
     env,
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
-use axum::{routing::get, Router};
+use anyhow::anyhow;
 use axum_client_ip::SecureClientIpSource;
-use lru::LruCache;
-use server::AppState;
-use sqlx::postgres::PgPoolOptions;
+use backend::{
+    blocklist, bulk,
+    card_source::{BulkSource, CardSource, CardSourceKind, FixtureSource, ScryfallSource},
+    db::{self, migrations, quarantine::AnomalyThresholds},
+    pow::{self, PowSettings},
+    rate_limit::{self, MemoryStore, PostgresStore, RateLimitStoreKind, RateLimiter, RateLimits},
+    reload, scryfall,
+    server::AppState,
+    util, ServerData,
+};
+use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing::info;
-use util::CollectionsJson;
-
-mod db;
-mod server;
-mod util;
 
 #[derive(serde::Deserialize)]
 struct Config {
     ip_source: SecureClientIpSource,
+    // Keys the hashes used to anonymise voters, keep it stable across restarts
+    server_secret: String,
+    // Enables the `/admin` routes when set
+    admin_token: Option<String>,
+    // Reads the catalogue from this file instead of the database and reloads it on change
+    collections_path: Option<PathBuf>,
+    #[serde(default = "default_snapshot_interval_secs")]
+    snapshot_interval_secs: u64,
+    // Recent votes are scanned for bursts every `ANOMALY_SCAN_INTERVAL_SECS`, looking back `ANOMALY_WINDOW_SECS`
+    #[serde(default = "default_anomaly_scan_interval_secs")]
+    anomaly_scan_interval_secs: u64,
+    #[serde(default = "default_anomaly_window_secs")]
+    anomaly_window_secs: u64,
+    // Identical votes on a card from one subnet within the window that get quarantined
+    #[serde(default = "default_anomaly_subnet_votes")]
+    anomaly_subnet_votes: i64,
+    // Identical votes on a card from anywhere within `ANOMALY_BURST_SECS` that get quarantined
+    #[serde(default = "default_anomaly_burst_votes")]
+    anomaly_burst_votes: i64,
+    #[serde(default = "default_anomaly_burst_secs")]
+    anomaly_burst_secs: u64,
+    // How often `blocked_clients` is reloaded, to pick up changes made elsewhere and drop expired entries
+    #[serde(default = "default_blocklist_refresh_secs")]
+    blocklist_refresh_secs: u64,
+    // Denied votes per minute from which proof of work difficulties are raised, unset keeps them as configured per collection
+    pow_pressure_denials: Option<u32>,
+    #[serde(default = "default_pow_max_difficulty")]
+    pow_max_difficulty: u8,
+    #[serde(default = "default_pow_challenge_ttl_secs")]
+    pow_challenge_ttl_secs: u64,
+    // Where collections get their cards from, `scryfall`, `bulk` or `fixtures`
+    #[serde(default)]
+    card_source: CardSourceKind,
+    // Directory with a `<collection_id>.json` per collection for the `fixtures` card source
+    card_fixtures_path: Option<PathBuf>,
+    // Scryfall bulk data file imported into the card store on startup
+    scryfall_bulk_path: Option<PathBuf>,
+    // Where rate limit buckets are kept, `memory` or `postgres`
+    #[serde(default)]
+    rate_limit_store: RateLimitStoreKind,
+    #[serde(flatten)]
+    rate_limits: RateLimits,
+}
+
+// Known values that would let anyone recompute voter keys and forge voter tokens or challenges
+const PLACEHOLDER_SECRETS: &[&str] = &["insecure"];
+const MIN_SECRET_LENGTH: usize = 16;
+
+impl Config {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.server_secret.len() < MIN_SECRET_LENGTH
+            || PLACEHOLDER_SECRETS.contains(&self.server_secret.as_str())
+        {
+            return Err(anyhow!(
+                "SERVER_SECRET must be a private random value of at least {} characters",
+                MIN_SECRET_LENGTH
+            ));
+        }
+        // `tokio::time::interval` panics on a zero period, which would silently end the job
+        if self.snapshot_interval_secs == 0 {
+            return Err(anyhow!("SNAPSHOT_INTERVAL_SECS must be greater than 0"));
+        }
+        if self.anomaly_scan_interval_secs == 0 {
+            return Err(anyhow!("ANOMALY_SCAN_INTERVAL_SECS must be greater than 0"));
+        }
+        if self.blocklist_refresh_secs == 0 {
+            return Err(anyhow!("BLOCKLIST_REFRESH_SECS must be greater than 0"));
+        }
+        // A single vote is never a burst, lower thresholds would quarantine every recent vote
+        if self.anomaly_subnet_votes < 2 || self.anomaly_burst_votes < 2 {
+            return Err(anyhow!(
+                "ANOMALY_SUBNET_VOTES and ANOMALY_BURST_VOTES must be at least 2"
+            ));
+        }
+        if self.anomaly_window_secs == 0 || self.anomaly_burst_secs == 0 {
+            return Err(anyhow!(
+                "ANOMALY_WINDOW_SECS and ANOMALY_BURST_SECS must be greater than 0"
+            ));
+        }
+        if self.pow_max_difficulty > pow::MAX_DIFFICULTY {
+            return Err(anyhow!(
+                "POW_MAX_DIFFICULTY must be at most {}",
+                pow::MAX_DIFFICULTY
+            ));
+        }
+        if self.pow_challenge_ttl_secs == 0
+            || self.pow_challenge_ttl_secs > pow::MAX_CHALLENGE_TTL.as_secs()
+        {
+            return Err(anyhow!(
+                "POW_CHALLENGE_TTL_SECS must be between 1 and {}",
+                pow::MAX_CHALLENGE_TTL.as_secs()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_snapshot_interval_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_anomaly_scan_interval_secs() -> u64 {
+    5 * 60
+}
+
+fn default_anomaly_window_secs() -> u64 {
+    60 * 60
+}
+
+fn default_anomaly_subnet_votes() -> i64 {
+    10
+}
+
+fn default_anomaly_burst_votes() -> i64 {
+    20
+}
+
+fn default_anomaly_burst_secs() -> u64 {
+    10
+}
+
+fn default_blocklist_refresh_secs() -> u64 {
+    60
+}
+
+fn default_pow_max_difficulty() -> u8 {
+    PowSettings::default().max_difficulty
+}
+
+fn default_pow_challenge_ttl_secs() -> u64 {
+    PowSettings::default().challenge_ttl.as_secs()
 }
 
-#[derive(Clone, Debug)]
-struct ServerData {
-    collections: CollectionsJson,
+fn build_card_source(config: &Config, pool: &PgPool) -> Result<Arc<dyn CardSource>, anyhow::Error> {
+    Ok(match config.card_source {
+        CardSourceKind::Scryfall => Arc::new(ScryfallSource(scryfall::ScryfallClient::default())),
+        CardSourceKind::Bulk => Arc::new(BulkSource(pool.clone())),
+        CardSourceKind::Fixtures => match &config.card_fixtures_path {
+            Some(path) => Arc::new(FixtureSource(path.clone())),
+            None => return Err(anyhow!("CARD_SOURCE=fixtures needs CARD_FIXTURES_PATH")),
+        },
+    })
 }
 
-impl std::fmt::Debug for AppState {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.server_data.fmt(f)
+fn build_rate_limiter(config: &Config, pool: &PgPool) -> RateLimiter {
+    match config.rate_limit_store {
+        RateLimitStoreKind::Memory => {
+            RateLimiter::new(Arc::new(MemoryStore::default()), config.rate_limits)
+        }
+        RateLimitStoreKind::Postgres => {
+            rate_limit::spawn_cleanup_job(pool.clone(), config.rate_limits);
+            RateLimiter::new(Arc::new(PostgresStore(pool.clone())), config.rate_limits)
+        }
     }
 }
 
+fn parse_version(version: &str) -> Result<i64, anyhow::Error> {
+    version
+        .parse::<i64>()
+        .map_err(|_| anyhow!("Invalid migration version '{}'", version))
+}
+
+async fn run_command(pool: &PgPool, args: &[String]) -> Result<(), anyhow::Error> {
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["migrate"] | ["migrate", "up"] => migrations::migrate_up(pool, None).await?,
+        ["migrate", "up", version] => {
+            migrations::migrate_up(pool, Some(parse_version(version)?)).await?
+        }
+        ["migrate", "down", version] => {
+            migrations::migrate_down(pool, parse_version(version)?).await?
+        }
+        ["migrate", "status"] => {
+            let applied = migrations::status(pool).await?;
+            for m in migrations::MIGRATIONS {
+                match applied.iter().find(|a| a.version == m.version) {
+                    Some(a) => info!("{} {} applied at {}", m.version, m.name, a.applied_at),
+                    None => info!("{} {} pending", m.version, m.name),
+                }
+            }
+        }
+        ["rebuild-ratings", rest @ ..] if rest.len() <= 1 => {
+            migrations::migrate_up(pool, None).await?;
+            let updated = db::lib::rebuild_ratings(pool, rest.first().copied()).await?;
+            info!("Rebuilt {} ratings rows from recorded votes", updated);
+        }
+        ["import-bulk", path] => {
+            migrations::migrate_up(pool, None).await?;
+            bulk::import_bulk_file(pool, Path::new(path)).await?;
+        }
+        _ => return Err(anyhow!("Unknown command '{}'", args.join(" "))),
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let subscriber = tracing_subscriber::fmt()
@@ -43,11 +236,8 @@ async fn main() -> Result<(), anyhow::Error> {
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config: Config = envy::from_env().unwrap();
-
-    let server_data = ServerData {
-        collections: util::parse_collections()?,
-    };
+    let config: Config = envy::from_env()?;
+    config.validate()?;
 
     let database_url = include_str!("../db/url.txt");
     let database_password = include_str!("../db/password.txt");
@@ -70,24 +260,72 @@ async fn main() -> Result<(), anyhow::Error> {
         .connect(&postgres_str)
         .await?;
 
-    db::init_db::init_db(&_pool, &server_data).await?;
+    // Maintenance commands, e.g. `backend migrate down 1` or `backend rebuild-ratings [collection_id]`
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        return run_command(&_pool, &args).await;
+    }
 
-    let app_state = AppState {
-        pool: _pool,
-        server_data,
-        post_rating_request_cache: Arc::new(Mutex::new(LruCache::new(
-            std::num::NonZeroUsize::new(20000).unwrap(),
-        ))),
+    migrations::migrate_up(&_pool, None).await?;
+    if let Some(path) = &config.scryfall_bulk_path {
+        bulk::import_bulk_file(&_pool, path).await?;
+    }
+    let applied_collections_file = match &config.collections_path {
+        Some(path) => Some(reload::apply_collections_file(&_pool, path).await?),
+        None => {
+            db::collections::seed_collections(&_pool, &util::parse_collections()?).await?;
+            None
+        }
+    };
+    let server_data = ServerData {
+        collections: db::collections::load_collections(&_pool).await?,
     };
-    // build our application with a single route
-    let app = Router::new()
-        .route(
-            "/ratings",
-            get(server::get_ratings).post(server::post_ratings),
+
+    let card_source = build_card_source(&config, &_pool)?;
+    db::init_db::init_db(&_pool, card_source.as_ref(), &server_data).await?;
+
+    let rate_limiter = build_rate_limiter(&config, &_pool);
+    let app_state = AppState {
+        pow: PowSettings {
+            pressure_denials: config.pow_pressure_denials,
+            max_difficulty: config.pow_max_difficulty,
+            challenge_ttl: Duration::from_secs(config.pow_challenge_ttl_secs),
+        },
+        ..AppState::new(
+            _pool,
+            server_data,
+            &config.server_secret,
+            config.admin_token.as_deref(),
+            card_source,
+            rate_limiter,
         )
-        .route("/collections", get(server::get_collections))
-        .layer(config.ip_source.into_extension())
-        .with_state(app_state);
+    };
+    db::snapshots::spawn_snapshot_job(
+        app_state.pool.clone(),
+        Duration::from_secs(config.snapshot_interval_secs),
+    );
+    db::quarantine::spawn_anomaly_job(
+        app_state.pool.clone(),
+        Duration::from_secs(config.anomaly_scan_interval_secs),
+        AnomalyThresholds {
+            window_secs: config.anomaly_window_secs as f64,
+            subnet_votes: config.anomaly_subnet_votes,
+            burst_votes: config.anomaly_burst_votes,
+            burst_secs: config.anomaly_burst_secs as f64,
+        },
+    );
+    pow::spawn_cleanup_job(app_state.pool.clone(), app_state.pow.challenge_ttl);
+    // Loaded before serving, so a broken blocklist fails the boot instead of leaving it empty
+    blocklist::reload(&app_state).await?;
+    blocklist::spawn_refresh_job(
+        app_state.clone(),
+        Duration::from_secs(config.blocklist_refresh_secs),
+    );
+    if let (Some(path), Some(applied)) = (config.collections_path, applied_collections_file) {
+        reload::watch_collections_file(app_state.clone(), path, applied);
+    }
+
+    let app = backend::router(app_state, config.ip_source);
 
     let address = env::var("ADDRESS").unwrap_or("127.0.0.1:8000".into());
     info!("Setup finished, starting listener on {}", address);
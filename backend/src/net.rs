@@ -0,0 +1,42 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+// Clients behind dual stack proxies may show up as IPv4 mapped IPv6 addresses, e.g. `::ffff:1.2.3.4`
+pub fn canonical_ip(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(x) => x.to_ipv4_mapped().map_or(*ip, IpAddr::V4),
+        IpAddr::V4(_) => *ip,
+    }
+}
+
+// The network a client controls, `ipv4_prefix` bits of an IPv4 address or the /64 of an IPv6 one
+pub fn client_network(ip: &IpAddr, ipv4_prefix: u8) -> IpNet {
+    let ip = canonical_ip(ip);
+    let prefix = match ip {
+        IpAddr::V4(_) => ipv4_prefix,
+        IpAddr::V6(_) => 64,
+    };
+    IpNet::new(ip, prefix)
+        .expect("prefix within the address length")
+        .trunc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_network() {
+        let network = |x: &str, prefix| client_network(&x.parse().unwrap(), prefix).to_string();
+
+        assert_eq!(network("192.168.0.1", 24), "192.168.0.0/24");
+        assert_eq!(network("::ffff:192.168.0.1", 24), "192.168.0.0/24");
+        assert_eq!(network("::ffff:192.168.0.1", 32), "192.168.0.1/32");
+        assert_eq!(network("2001:db8:0:1:ffff::2", 24), "2001:db8:0:1::/64");
+        assert_eq!(
+            canonical_ip(&"::ffff:10.0.0.1".parse().unwrap()),
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{error, info};
+
+use crate::{db::challenges, error::ApiError, server::AppState, signing, util::Collection};
+
+// Highest difficulty a collection can be set to, solving takes around 2^difficulty hashes
+pub const MAX_DIFFICULTY: u8 = 32;
+
+// Spent challenges are kept until they expire, and far off expiry dates overflow the timestamp
+pub const MAX_CHALLENGE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const MAX_SOLUTION_LENGTH: usize = 64;
+
+// Bits a challenge may fall behind the current difficulty, so votes in flight survive a raise
+const MAX_DIFFICULTY_LAG: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PowSettings {
+    // Denied votes per minute at which every difficulty goes up a bit, and another bit per doubling. `None` never raises them
+    pub pressure_denials: Option<u32>,
+    // Raised difficulties stop here, collections configured higher keep theirs
+    pub max_difficulty: u8,
+    pub challenge_ttl: Duration,
+}
+
+impl Default for PowSettings {
+    fn default() -> Self {
+        PowSettings {
+            pressure_denials: None,
+            max_difficulty: 20,
+            challenge_ttl: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl PowSettings {
+    pub fn difficulty(&self, base: u8, denials_per_minute: u32) -> u8 {
+        let raise = match self.pressure_denials {
+            Some(threshold) if threshold > 0 && denials_per_minute >= threshold => {
+                1 + (denials_per_minute / threshold).ilog2() as u8
+            }
+            _ => 0,
+        };
+        base.max(base.saturating_add(raise).min(self.max_difficulty))
+    }
+}
+
+// Difficulty of new challenges for the collection, 0 when votes need none
+pub fn current_difficulty(state: &AppState, collection: &Collection) -> u8 {
+    state.pow.difficulty(
+        collection.pow_difficulty,
+        state.rate_limiter.vote_pressure.denials_per_minute(),
+    )
+}
+
+#[derive(Debug, Serialize)]
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty: u8,
+    pub expires_at: DateTime<Utc>,
+}
+
+// `<nonce>.<expiry>.<difficulty>.<collection_id>.<signature>`, collection ids never contain dots
+pub fn issue_challenge(
+    secret: &[u8],
+    collection_id: &str,
+    difficulty: u8,
+    ttl: Duration,
+) -> Challenge {
+    let expires_at = Utc::now() + ttl;
+    let payload = format!(
+        "{}.{}.{}.{}",
+        hex::encode(rand::random::<[u8; 16]>()),
+        expires_at.timestamp(),
+        difficulty,
+        collection_id
+    );
+    Challenge {
+        challenge: format!("{}.{}", payload, challenge_signature(secret, &payload)),
+        difficulty,
+        expires_at,
+    }
+}
+
+fn challenge_signature(secret: &[u8], payload: &str) -> String {
+    // Prefixed so a challenge signature can never double as a voter token or key
+    signing::hmac_hex(secret, format!("challenge:{}", payload).as_bytes())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SignedChallenge<'a> {
+    nonce: &'a str,
+    expires_at: DateTime<Utc>,
+    difficulty: u8,
+    collection_id: &'a str,
+}
+
+fn verify_challenge<'a>(secret: &[u8], challenge: &'a str) -> Option<SignedChallenge<'a>> {
+    let (payload, signature) = challenge.rsplit_once('.')?;
+    if !signing::constant_time_eq(
+        challenge_signature(secret, payload).as_bytes(),
+        signature.as_bytes(),
+    ) {
+        return None;
+    }
+    let mut parts = payload.splitn(4, '.');
+    Some(SignedChallenge {
+        nonce: parts.next()?,
+        expires_at: Utc.timestamp_opt(parts.next()?.parse().ok()?, 0).single()?,
+        difficulty: parts.next()?.parse().ok()?,
+        collection_id: parts.next()?,
+    })
+}
+
+// Leading zero bits of SHA-256(`<challenge>:<solution>`)
+pub fn solution_bits(challenge: &str, solution: &str) -> u32 {
+    let hash = Sha256::digest(format!("{}:{}", challenge, solution).as_bytes());
+    let mut bits = 0;
+    for x in hash {
+        bits += x.leading_zeros();
+        if x != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+// Lets the vote through if the collection needs no proof of work right now, or the challenge is valid and solved.
+// The challenge is returned to be spent along with the vote, see `spend_challenge`.
+// A challenge keeps the difficulty it was issued with if the collection's has only gone up a little since,
+// so easy challenges collected while things were calm can't be spent once the difficulty is raised
+pub fn check_solution<'a>(
+    state: &AppState,
+    collection_id: &str,
+    collection: &Collection,
+    challenge: Option<&'a str>,
+    solution: Option<&str>,
+) -> Result<Option<SignedChallenge<'a>>, ApiError> {
+    let difficulty = current_difficulty(state, collection);
+    if difficulty == 0 {
+        return Ok(None);
+    }
+    let (Some(challenge), Some(solution)) = (challenge, solution) else {
+        return Err(ApiError::ChallengeRequired(difficulty));
+    };
+
+    let invalid = |x: &str| Err(ApiError::InvalidChallenge(x.into()));
+    let Some(signed) = verify_challenge(state.server_secret.as_bytes(), challenge) else {
+        return invalid("Invalid challenge");
+    };
+    if signed.collection_id != collection_id {
+        return invalid("Challenge is for another collection");
+    }
+    if signed.expires_at < Utc::now() {
+        return invalid("Challenge expired");
+    }
+    let minimum = difficulty
+        .saturating_sub(MAX_DIFFICULTY_LAG)
+        .max(collection.pow_difficulty)
+        .max(1);
+    if signed.difficulty < minimum {
+        return invalid("Challenge is too easy by now");
+    }
+    if solution.len() > MAX_SOLUTION_LENGTH
+        || solution_bits(challenge, solution) < signed.difficulty as u32
+    {
+        return invalid("Wrong solution");
+    }
+
+    Ok(Some(signed))
+}
+
+// Spends a checked challenge with the vote it paid for, the vote is rolled back with the transaction if it was used before
+pub async fn spend_challenge(
+    tx: &mut Transaction<'_, Postgres>,
+    signed: &SignedChallenge<'_>,
+) -> Result<(), ApiError> {
+    match challenges::spend_challenge(tx, signed.nonce, signed.expires_at).await? {
+        true => Ok(()),
+        false => Err(ApiError::InvalidChallenge("Challenge already used".into())),
+    }
+}
+
+pub fn spawn_cleanup_job(pool: PgPool, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period.max(Duration::from_secs(60)));
+        loop {
+            interval.tick().await;
+            match challenges::delete_expired_challenges(&pool).await {
+                Ok(n) => info!("Dropped {} expired challenges", n),
+                Err(e) => error!("Failed to drop expired challenges: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_difficulty() {
+        let settings = PowSettings {
+            pressure_denials: Some(10),
+            max_difficulty: 16,
+            ..Default::default()
+        };
+
+        assert_eq!(settings.difficulty(0, 9), 0);
+        assert_eq!(settings.difficulty(0, 10), 1);
+        assert_eq!(settings.difficulty(8, 19), 9);
+        assert_eq!(settings.difficulty(8, 40), 11);
+        assert_eq!(settings.difficulty(8, u32::MAX), 16);
+        assert_eq!(settings.difficulty(24, u32::MAX), 24);
+        assert_eq!(PowSettings::default().difficulty(8, u32::MAX), 8);
+    }
+
+    #[test]
+    fn test_challenge() {
+        let x = issue_challenge(b"secret", "tst", 4, Duration::from_secs(60));
+        let signed = verify_challenge(b"secret", &x.challenge).unwrap();
+
+        assert_eq!(signed.nonce.len(), 32);
+        assert_eq!(signed.expires_at.timestamp(), x.expires_at.timestamp());
+        assert_eq!((signed.difficulty, signed.collection_id), (4, "tst"));
+        assert_eq!(verify_challenge(b"other secret", &x.challenge), None);
+        let forged = x.challenge.replacen(".4.tst.", ".0.tst.", 1);
+        assert_eq!(verify_challenge(b"secret", &forged), None);
+    }
+
+    #[test]
+    fn test_solution_bits() {
+        let solution = (0..)
+            .map(|x: u32| x.to_string())
+            .find(|x| solution_bits("challenge", x) >= 8)
+            .unwrap();
+        let hash = Sha256::digest(format!("challenge:{}", solution).as_bytes());
+
+        assert_eq!(hash[0], 0);
+        assert_eq!(
+            solution_bits("challenge", &solution),
+            8 + hash[1].leading_zeros()
+        );
+    }
+}
@@ -0,0 +1,548 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_client_ip::SecureClientIp;
+use ipnet::IpNet;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::{
+    db::rate_limits,
+    error::ApiError,
+    net,
+    server::AppState,
+    validation::{CardCode, CollectionId, SetCode},
+};
+
+// `<requests>/<seconds>`, e.g. `30/60` allows bursts of 30 requests and refills them over a minute
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Limit {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl Limit {
+    pub const fn new(capacity: u32, period_secs: u64) -> Self {
+        Limit {
+            capacity,
+            period: Duration::from_secs(period_secs),
+        }
+    }
+
+    fn refill_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+
+    // Time until `tokens` have grown back to `target`
+    fn time_until(&self, tokens: f64, target: f64) -> Duration {
+        Duration::from_secs_f64(((target - tokens) / self.refill_per_second()).max(0.0))
+    }
+}
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (capacity, seconds) = value.split_once('/')?;
+            Some((
+                capacity.trim().parse::<u32>().ok()?,
+                seconds.trim().parse::<u64>().ok()?,
+            ))
+        };
+        match parse() {
+            Some((capacity, seconds)) if capacity > 0 && seconds > 0 => {
+                Ok(Limit::new(capacity, seconds))
+            }
+            _ => Err(format!(
+                "Invalid rate limit '{}', expected `<requests>/<seconds>`",
+                value
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Limit {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// Limits per group of routes, each read from `RATE_LIMIT_<NAME>`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimits {
+    // `POST` and `DELETE /ratings` per client
+    #[serde(default = "default_votes_per_ip")]
+    pub rate_limit_votes_per_ip: Limit,
+    // The same, per client and card across formats
+    #[serde(default = "default_votes_per_card")]
+    pub rate_limit_votes_per_card: Limit,
+    // Every other route per client
+    #[serde(default = "default_reads_per_ip")]
+    pub rate_limit_reads_per_ip: Limit,
+}
+
+fn default_votes_per_ip() -> Limit {
+    Limit::new(120, 60)
+}
+
+fn default_votes_per_card() -> Limit {
+    Limit::new(20, 60 * 60)
+}
+
+fn default_reads_per_ip() -> Limit {
+    Limit::new(600, 60)
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            rate_limit_votes_per_ip: default_votes_per_ip(),
+            rate_limit_votes_per_card: default_votes_per_card(),
+            rate_limit_reads_per_ip: default_reads_per_ip(),
+        }
+    }
+}
+
+// Token buckets by key, picked once at startup through `RATE_LIMIT_STORE`
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    // Takes a token from the bucket if it has a whole one, returns the tokens left and whether one was taken
+    async fn take(&self, key: &str, limit: &Limit) -> Result<(f64, bool), Error>;
+}
+
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    // Per process, reset on restart
+    #[default]
+    Memory,
+    // Shared by all replicas and kept across restarts
+    Postgres,
+}
+
+// Hard cap on the buckets of the memory store, reaching it evicts down to half
+const MEMORY_STORE_MAX_BUCKETS: usize = 200_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: Limit,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        (self.tokens + (now - self.updated).as_secs_f64() * self.limit.refill_per_second())
+            .min(self.limit.capacity as f64)
+    }
+
+    // Share of its capacity the bucket holds, full buckets can be dropped without changing anything
+    fn fullness(&self, now: Instant) -> f64 {
+        self.refilled(now) / self.limit.capacity as f64
+    }
+}
+
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    max_buckets: usize,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::with_max_buckets(MEMORY_STORE_MAX_BUCKETS)
+    }
+}
+
+impl MemoryStore {
+    pub fn with_max_buckets(max_buckets: usize) -> Self {
+        MemoryStore {
+            buckets: Mutex::default(),
+            max_buckets: max_buckets.max(2),
+        }
+    }
+}
+
+// Drops full buckets, then the fullest ones until half the cap is left.
+// Each eviction makes room for as many new buckets as it looked at, so its cost is spread over those requests
+fn evict(buckets: &mut HashMap<String, Bucket>, max_buckets: usize, now: Instant) {
+    buckets.retain(|_, x| x.fullness(now) < 1.0);
+    let keep = max_buckets / 2;
+    if buckets.len() <= keep {
+        return;
+    }
+
+    let mut by_fullness = buckets
+        .iter()
+        .map(|(key, x)| (x.fullness(now), key.clone()))
+        .collect::<Vec<_>>();
+    by_fullness.select_nth_unstable_by(keep, |a, b| a.0.total_cmp(&b.0));
+    for (_, key) in &by_fullness[keep..] {
+        buckets.remove(key);
+    }
+    warn!(
+        "Rate limit store reached {} buckets, evicted the fullest",
+        max_buckets
+    );
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn take(&self, key: &str, limit: &Limit) -> Result<(f64, bool), Error> {
+        let now = Instant::now();
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| anyhow!("Rate limit store poisoned"))?;
+        if buckets.len() >= self.max_buckets && !buckets.contains_key(key) {
+            evict(&mut buckets, self.max_buckets, now);
+        }
+        let tokens = buckets
+            .get(key)
+            .map_or(limit.capacity as f64, |x| x.refilled(now));
+        let allowed = tokens >= 1.0;
+        let tokens = if allowed { tokens - 1.0 } else { tokens };
+        buckets.insert(
+            key.to_owned(),
+            Bucket {
+                tokens,
+                updated: now,
+                limit: *limit,
+            },
+        );
+
+        Ok((tokens, allowed))
+    }
+}
+
+pub struct PostgresStore(pub PgPool);
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn take(&self, key: &str, limit: &Limit) -> Result<(f64, bool), Error> {
+        Ok(rate_limits::take_token(
+            &self.0,
+            key,
+            limit.capacity as f64,
+            limit.refill_per_second(),
+        )
+        .await?)
+    }
+}
+
+// Drops idle buckets of the postgres store once they'd be full again anyway
+pub fn spawn_cleanup_job(pool: PgPool, limits: RateLimits) {
+    let idle = [
+        limits.rate_limit_votes_per_ip,
+        limits.rate_limit_votes_per_card,
+        limits.rate_limit_reads_per_ip,
+    ]
+    .iter()
+    .map(|x| x.period)
+    .max()
+    .unwrap_or_default();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(idle.max(Duration::from_secs(60)));
+        loop {
+            interval.tick().await;
+            match rate_limits::delete_idle_buckets(&pool, idle.as_secs_f64()).await {
+                Ok(n) => info!("Dropped {} idle rate limit buckets", n),
+                Err(e) => error!("Failed to drop idle rate limit buckets: {}", e),
+            }
+        }
+    });
+}
+
+// Outcome of the most restrictive bucket a request was checked against
+#[derive(Debug, Clone, Copy)]
+struct Decision {
+    allowed: bool,
+    limit: Limit,
+    tokens: f64,
+}
+
+impl Decision {
+    // `RateLimit-*` headers of the IETF draft, plus `Retry-After` when rejected
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let seconds = |x: Duration| HeaderValue::from(x.as_secs_f64().ceil() as u64);
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit.capacity));
+        headers.insert(
+            "ratelimit-remaining",
+            HeaderValue::from(self.tokens.floor() as u32),
+        );
+        headers.insert(
+            "ratelimit-reset",
+            seconds(
+                self.limit
+                    .time_until(self.tokens, self.limit.capacity as f64),
+            ),
+        );
+        if !self.allowed {
+            headers.insert(
+                header::RETRY_AFTER,
+                seconds(self.limit.time_until(self.tokens, 1.0)),
+            );
+        }
+        headers
+    }
+}
+
+// Requests denied across all clients in the current and the previous minute
+pub struct Pressure {
+    started: Instant,
+    // Minute since `started`, denials in it and denials in the minute before
+    window: Mutex<(u64, u32, u32)>,
+}
+
+impl Default for Pressure {
+    fn default() -> Self {
+        Pressure {
+            started: Instant::now(),
+            window: Mutex::new((0, 0, 0)),
+        }
+    }
+}
+
+impl Pressure {
+    fn roll(&self, window: &mut (u64, u32, u32)) {
+        let minute = self.started.elapsed().as_secs() / 60;
+        *window = match minute - window.0 {
+            0 => *window,
+            1 => (minute, 0, window.1),
+            _ => (minute, 0, 0),
+        };
+    }
+
+    fn record_denial(&self) {
+        if let Ok(mut window) = self.window.lock() {
+            self.roll(&mut window);
+            window.1 = window.1.saturating_add(1);
+        }
+    }
+
+    // Denials of the busier of the two minutes, so it doesn't drop to zero at every full minute
+    pub fn denials_per_minute(&self) -> u32 {
+        match self.window.lock() {
+            Ok(mut window) => {
+                self.roll(&mut window);
+                window.1.max(window.2)
+            }
+            Err(_) => 0,
+        }
+    }
+}
+
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    pub limits: RateLimits,
+    // Denied votes, the proof of work gets harder while they pile up
+    pub vote_pressure: Pressure,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(Arc::new(MemoryStore::default()), RateLimits::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, limits: RateLimits) -> Self {
+        RateLimiter {
+            store,
+            limits,
+            vote_pressure: Pressure::default(),
+        }
+    }
+
+    // Takes a token from every bucket, store failures let the request through rather than taking the site down
+    async fn check(&self, buckets: &[(String, Limit)]) -> Option<Decision> {
+        let mut decision: Option<Decision> = None;
+        for (key, limit) in buckets {
+            let (tokens, allowed) = match self.store.take(key, limit).await {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Rate limit store failed, letting request through: {:#}", e);
+                    continue;
+                }
+            };
+            let x = Decision {
+                allowed,
+                limit: *limit,
+                tokens,
+            };
+            decision = match decision {
+                Some(d) if !d.allowed || (x.allowed && d.tokens <= x.tokens) => Some(d),
+                _ => Some(x),
+            };
+        }
+
+        decision
+    }
+
+    async fn run(
+        &self,
+        buckets: &[(String, Limit)],
+        pressure: Option<&Pressure>,
+        request: Request,
+        next: Next,
+    ) -> Response {
+        let Some(decision) = self.check(buckets).await else {
+            return next.run(request).await;
+        };
+
+        let mut response = match decision.allowed {
+            true => next.run(request).await,
+            false => {
+                if let Some(x) = pressure {
+                    x.record_denial();
+                }
+                ApiError::RateLimited.into_response()
+            }
+        };
+        response.headers_mut().extend(decision.headers());
+        response
+    }
+}
+
+#[derive(Deserialize)]
+struct CardKey {
+    collection_id: CollectionId,
+    set_code: SetCode,
+    card_code: CardCode,
+}
+
+// IPv6 clients get a whole /64 per connection for free, so they share buckets per /64
+fn client_key(ip: &IpAddr) -> String {
+    match net::client_network(ip, 32) {
+        IpNet::V4(x) => x.addr().to_string(),
+        x => x.to_string(),
+    }
+}
+
+// Votes, per client and per client and card
+pub async fn limit_votes(
+    State(state): State<AppState>,
+    ip: SecureClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limits = &state.rate_limiter.limits;
+    let client = client_key(&ip.0);
+    let mut buckets = vec![(format!("votes:{}", client), limits.rate_limit_votes_per_ip)];
+    // Malformed requests only count against the client, the handler rejects them anyway
+    if let Ok(Query(card)) = Query::<CardKey>::try_from_uri(request.uri()) {
+        buckets.push((
+            format!(
+                "card:{}:{}:{}:{}",
+                client, *card.collection_id, *card.set_code, *card.card_code
+            ),
+            limits.rate_limit_votes_per_card,
+        ));
+    }
+
+    let pressure = Some(&state.rate_limiter.vote_pressure);
+    state
+        .rate_limiter
+        .run(&buckets, pressure, request, next)
+        .await
+}
+
+pub async fn limit_reads(
+    State(state): State<AppState>,
+    ip: SecureClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let buckets = [(
+        format!("reads:{}", client_key(&ip.0)),
+        state.rate_limiter.limits.rate_limit_reads_per_ip,
+    )];
+
+    state.rate_limiter.run(&buckets, None, request, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!("30/60".parse(), Ok(Limit::new(30, 60)));
+        assert_eq!(" 5 / 3600 ".parse(), Ok(Limit::new(5, 3600)));
+        assert!("30".parse::<Limit>().is_err());
+        assert!("0/60".parse::<Limit>().is_err());
+        assert!("30/0".parse::<Limit>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryStore::default();
+        let limit = Limit::new(2, 3600);
+
+        assert!(store.take("a", &limit).await.unwrap().1);
+        assert!(store.take("a", &limit).await.unwrap().1);
+        let (tokens, allowed) = store.take("a", &limit).await.unwrap();
+        assert!(!allowed);
+        assert!(tokens < 1.0);
+        assert_eq!(store.take("b", &limit).await.unwrap(), (1.0, true));
+
+        let decision = Decision {
+            allowed,
+            limit,
+            tokens,
+        };
+        let headers = decision.headers();
+        assert_eq!(headers["ratelimit-limit"], "2");
+        assert_eq!(headers["ratelimit-remaining"], "0");
+        assert!(headers.contains_key(header::RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_eviction() {
+        let store = MemoryStore::with_max_buckets(4);
+        let limit = Limit::new(2, 3600);
+
+        for key in ["a", "b", "c"] {
+            store.take(key, &limit).await.unwrap();
+        }
+        store.take("a", &limit).await.unwrap();
+        store.take("d", &limit).await.unwrap();
+        // The fuller half goes, the drained bucket of `a` stays
+        store.take("e", &limit).await.unwrap();
+        {
+            let buckets = store.buckets.lock().unwrap();
+            assert_eq!(buckets.len(), 3);
+            assert!(buckets["a"].tokens < 1.0);
+        }
+        assert!(!store.take("a", &limit).await.unwrap().1);
+    }
+
+    #[test]
+    fn test_client_key() {
+        let key = |x: &str| client_key(&x.parse().unwrap());
+
+        assert_eq!(key("192.168.0.1"), "192.168.0.1");
+        assert_eq!(key("::ffff:192.168.0.1"), "192.168.0.1");
+        assert_eq!(key("2001:db8:0:1::1"), key("2001:db8:0:1:ffff::2"));
+        assert_ne!(key("2001:db8:0:1::1"), key("2001:db8:0:2::1"));
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::{
+    db::{collections, init_db},
+    server::AppState,
+    util::{self, CollectionsJson},
+    ServerData,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+fn parse(content: &[u8]) -> Result<CollectionsJson, Error> {
+    let collections = serde_json::from_slice::<CollectionsJson>(content)?;
+    let errors = util::validate_collections(&collections);
+    if !errors.is_empty() {
+        return Err(anyhow!(errors.join("; ")));
+    }
+
+    Ok(collections)
+}
+
+// Writes the collections file into the catalogue tables on startup, an invalid file fails the boot
+// Returns the applied content so the watcher only reacts to later changes
+pub async fn apply_collections_file(pool: &PgPool, path: &Path) -> Result<Vec<u8>, Error> {
+    let content = tokio::fs::read(path).await?;
+    let collections = parse(&content)
+        .map_err(|e| anyhow!("Invalid collections file {}: {}", path.display(), e))?;
+
+    info!("Loading collections from {}", path.display());
+    collections::replace_collections(pool, &collections).await?;
+
+    Ok(content)
+}
+
+async fn reload(state: &AppState, path: &Path, content: &[u8]) -> Result<(), Error> {
+    let _guard = collections::CATALOGUE_WRITE.lock().await;
+
+    let current = state.server_data.load_full();
+    let new = match serde_json::from_slice::<CollectionsJson>(content) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(
+                "Rejected {}, keeping the current collections: {}",
+                path.display(),
+                e
+            );
+            return Ok(());
+        }
+    };
+
+    let diff = util::diff_collections(&current.collections, &new);
+    let errors = util::validate_collections(&new);
+    if !errors.is_empty() {
+        warn!(
+            "Rejected {}, keeping the current collections: {}\nRejected changes:\n{}",
+            path.display(),
+            errors.join("; "),
+            diff.join("\n")
+        );
+        return Ok(());
+    }
+    if diff.is_empty() {
+        return Ok(());
+    }
+
+    info!("Applying {}:\n{}", path.display(), diff.join("\n"));
+
+    // A new format needs rows for every collection, otherwise only new or re-queried collections are fetched
+    let new_format = new.formats.iter().any(|f| {
+        current
+            .collections
+            .formats
+            .iter()
+            .all(|x| x.title != f.title)
+    });
+    let to_register = new
+        .entries
+        .iter()
+        .filter(|(id, c)| {
+            new_format
+                || match current.collections.entries.get(*id) {
+                    None => true,
+                    Some(old) => {
+                        old.scryfall_query != c.scryfall_query
+                            || old.excluded_formats != c.excluded_formats
+                            || (c.releasing && !old.releasing)
+                    }
+                }
+        })
+        .map(|(id, _)| id.clone())
+        .collect::<Vec<_>>();
+    if !to_register.is_empty() {
+        init_db::register_collections(&state.pool, state.card_source.as_ref(), &new, &to_register)
+            .await?;
+        info!("Registered collections {:?}", to_register);
+    }
+    // Only once the cards are in, so a failed registration leaves both the catalogue tables and `ServerData`
+    // as they were and the next attempt sees the same diff. `ratings` rows of unlisted collections are never read
+    collections::replace_collections(&state.pool, &new).await?;
+    state
+        .server_data
+        .store(Arc::new(ServerData { collections: new }));
+
+    Ok(())
+}
+
+// Polls the collections file and swaps in every valid change, invalid files are logged and ignored
+pub fn watch_collections_file(state: AppState, path: PathBuf, applied: Vec<u8>) {
+    tokio::spawn(async move {
+        let mut last = applied;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let content = match tokio::fs::read(&path).await {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Failed to read {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if content == last {
+                continue;
+            }
+
+            // Failures are retried on the next tick, the file counts as applied once it went through
+            match reload(&state, &path, &content).await {
+                Ok(()) => last = content,
+                Err(e) => error!("Failed to apply {}, retrying: {}", path.display(), e),
+            }
+        }
+    });
+}
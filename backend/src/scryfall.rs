@@ -0,0 +1,281 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::util::CardDetail;
+
+const API_URL: &str = "https://api.scryfall.com";
+
+// Scryfall asks for an identifying user agent on every request, see https://scryfall.com/docs/api
+const USER_AGENT: &str = concat!("mtg-rater-backend/", env!("CARGO_PKG_VERSION"));
+
+// Scryfall allows about ten requests per second
+const PAGE_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum ScryfallError {
+    // The query is valid but matches no cards
+    NotFound(String),
+    // Scryfall was unreachable, also after retrying
+    Network(reqwest::Error),
+    // Any other non success response, e.g. a malformed query
+    Status(StatusCode, String),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for ScryfallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScryfallError::NotFound(details) => write!(f, "scryfall found no cards: {}", details),
+            ScryfallError::Network(e) => write!(f, "scryfall unreachable: {}", e),
+            ScryfallError::Status(status, details) => {
+                write!(f, "scryfall responded {}: {}", status, details)
+            }
+            ScryfallError::Parse(e) => write!(f, "unexpected scryfall response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScryfallError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScryfallError::Network(e) => Some(e),
+            ScryfallError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CardPage {
+    data: Vec<CardDetail>,
+    #[serde(default)]
+    has_more: bool,
+    next_page: Option<String>,
+}
+
+// Body of every scryfall error response
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    details: String,
+}
+
+#[derive(Clone)]
+pub struct ScryfallClient {
+    http: reqwest::Client,
+    base_url: String,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for ScryfallClient {
+    fn default() -> Self {
+        ScryfallClient::new(API_URL)
+    }
+}
+
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+impl ScryfallClient {
+    pub fn new(base_url: &str) -> Self {
+        ScryfallClient {
+            http: reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(Duration::from_secs(30))
+                .build()
+                .expect("static client configuration"),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    // Fetches one page, retrying rate limits, server errors and network failures with exponential backoff
+    async fn get_page(&self, url: &str) -> Result<CardPage, ScryfallError> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let delay = match self.http.get(url).send().await {
+                Ok(response) if response.status().is_success() => {
+                    let body = response.bytes().await.map_err(ScryfallError::Network)?;
+                    return serde_json::from_slice(&body).map_err(ScryfallError::Parse);
+                }
+                Ok(response)
+                    if response.status() == StatusCode::TOO_MANY_REQUESTS
+                        || response.status().is_server_error() =>
+                {
+                    if attempt > self.max_retries {
+                        return Err(ScryfallError::Status(
+                            response.status(),
+                            "gave up after retrying".into(),
+                        ));
+                    }
+                    warn!("Scryfall responded {}, retrying", response.status());
+                    // A misbehaving Retry-After must not stall the import indefinitely
+                    retry_after(&response)
+                        .unwrap_or(backoff)
+                        .min(self.max_backoff)
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.bytes().await.unwrap_or_default();
+                    let details = serde_json::from_slice::<ErrorBody>(&body)
+                        .map(|x| x.details)
+                        .unwrap_or_default();
+                    return Err(match status {
+                        StatusCode::NOT_FOUND => ScryfallError::NotFound(details),
+                        _ => ScryfallError::Status(status, details),
+                    });
+                }
+                Err(e) if attempt > self.max_retries => return Err(ScryfallError::Network(e)),
+                Err(e) => {
+                    warn!("Scryfall request failed, retrying: {}", e);
+                    backoff
+                }
+            };
+
+            tokio::time::sleep(delay).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+
+    // Every printing matching the query, once per card like the search page with `unique=cards`
+    #[tracing::instrument(skip(self))]
+    pub async fn search(&self, scryfall_query: &str) -> Result<Vec<CardDetail>, ScryfallError> {
+        // Keyed by printing, the same card can show up on consecutive pages
+        let mut cards: HashMap<(String, String), CardDetail> = HashMap::new();
+        let mut url = format!(
+            "{}/cards/search?q=-is%3Adigital+{}&order=set&unique=cards",
+            self.base_url, scryfall_query
+        );
+        loop {
+            info!(url);
+            let page = self.get_page(&url).await?;
+            cards.extend(
+                page.data
+                    .into_iter()
+                    .map(|x| ((x.set.clone(), x.collector_number.clone()), x)),
+            );
+
+            match page.next_page {
+                Some(next) if page.has_more => url = next,
+                _ => break,
+            }
+            tokio::time::sleep(PAGE_DELAY).await;
+        }
+
+        Ok(cards.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::{
+        extract::State,
+        http::{header, HeaderMap},
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+
+    use super::*;
+
+    // Rate limits the first request, then serves two pages
+    async fn search(
+        State((calls, base)): State<(Arc<AtomicUsize>, String)>,
+        headers: HeaderMap,
+    ) -> axum::response::Response {
+        assert!(headers[header::USER_AGENT]
+            .to_str()
+            .unwrap()
+            .starts_with("mtg-rater-backend/"));
+        match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => (
+                axum::http::StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, "3600")],
+            )
+                .into_response(),
+            1 => axum::Json(serde_json::json!({
+                "data": [{"set": "otj", "collector_number": "1"}],
+                "has_more": true,
+                "next_page": format!("{}/cards/search?page=2", base),
+            }))
+            .into_response(),
+            _ => axum::Json(serde_json::json!({
+                "data": [{"set": "otj", "collector_number": "2"}],
+                "has_more": false,
+            }))
+            .into_response(),
+        }
+    }
+
+    async fn not_found() -> axum::response::Response {
+        (
+            axum::http::StatusCode::NOT_FOUND,
+            axum::Json(
+                serde_json::json!({"object": "error", "code": "not_found", "details": "no cards"}),
+            ),
+        )
+            .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/cards/search", get(search))
+            .route("/missing/cards/search", get(not_found))
+            .with_state((calls.clone(), base.clone()));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // The hour long Retry-After is clamped to the maximum backoff
+        let client = ScryfallClient {
+            max_backoff: Duration::from_millis(10),
+            ..ScryfallClient::new(&base)
+        };
+        let mut cards = tokio::time::timeout(Duration::from_secs(5), client.search("set%3Aotj"))
+            .await
+            .expect("Retry-After is clamped")
+            .unwrap();
+        cards.sort_by(|a, b| a.collector_number.cmp(&b.collector_number));
+        assert_eq!(
+            cards
+                .iter()
+                .map(|x| x.collector_number.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1", "2"]
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        match ScryfallClient::new(&format!("{}/missing", base))
+            .search("set%3Anope")
+            .await
+        {
+            Err(ScryfallError::NotFound(details)) => assert_eq!(details, "no cards"),
+            x => panic!("expected not found, got {:?}", x.map(|x| x.len())),
+        }
+    }
+}
@@ -1,47 +1,214 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
 
-use anyhow::anyhow;
+use arc_swap::ArcSwap;
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
     Json,
 };
 use axum_client_ip::SecureClientIp;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 use tracing::{info, instrument};
 
 use crate::{
+    blocklist::Blocklist,
+    card_source::CardSource,
     db::{
+        cards::{self, SchemaCard},
         init_db,
-        lib::{self, RatingsValue, SchemaRatings},
+        lib::{self, RatingsFilter, RatingsValue, SchemaRatings, Voter},
+        snapshots::{self, SchemaSnapshot},
     },
-    util::{CardDetail, Collection},
+    error::ApiError,
+    export::{self, ExportFormat},
+    pow::{self, Challenge, PowSettings},
+    rate_limit::RateLimiter,
+    signing,
+    stats::{self, RankMethod, RankScore, RatingStats},
+    summary::{self, Summary},
+    util::{self, CardDetail, Collection},
+    validation::{CardCode, CollectionId, FormatId, SetCode, ValidPath, ValidQuery},
     ServerData,
 };
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: Pool<Postgres>,
-    pub server_data: ServerData,
-    pub post_rating_request_cache: Arc<Mutex<lru::LruCache<String, usize>>>,
+    // Swapped out as a whole whenever the catalogue changes at runtime
+    pub server_data: Arc<ArcSwap<ServerData>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub server_secret: Arc<str>,
+    pub admin_token: Option<Arc<str>>,
+    pub card_source: Arc<dyn CardSource>,
+    // Networks that may not vote, empty until loaded from `blocked_clients`
+    pub blocklist: Arc<ArcSwap<Blocklist>>,
+    pub pow: PowSettings,
+}
+
+impl AppState {
+    pub fn new(
+        pool: Pool<Postgres>,
+        server_data: ServerData,
+        server_secret: &str,
+        admin_token: Option<&str>,
+        card_source: Arc<dyn CardSource>,
+        rate_limiter: RateLimiter,
+    ) -> Self {
+        AppState {
+            pool,
+            server_data: Arc::new(ArcSwap::from_pointee(server_data)),
+            rate_limiter: Arc::new(rate_limiter),
+            server_secret: server_secret.into(),
+            admin_token: admin_token.map(Into::into),
+            card_source,
+            blocklist: Default::default(),
+            pow: PowSettings::default(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct RatingsCollectionExtractor {
-    collection_id: String,
+    collection_id: CollectionId,
 }
 
 #[derive(Deserialize)]
 pub struct RatingsPostExtractor {
     rating: String,
-    card_code: String,
-    set_code: String,
-    format_id: String,
+    card_code: CardCode,
+    set_code: SetCode,
+    format_id: FormatId,
+}
+
+// Proof of work for collections that currently require one, see `GET /challenge`
+#[derive(Deserialize)]
+pub struct RatingsChallengeExtractor {
+    challenge: Option<String>,
+    solution: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RatingsDeleteExtractor {
+    card_code: CardCode,
+    set_code: SetCode,
+    format_id: FormatId,
+}
+
+const VOTER_TOKEN_COOKIE: &str = "voter_token";
+const VOTER_TOKEN_HEADER: &str = "x-voter-token";
+
+// The voter id of a validly signed `X-Voter-Token` header or `voter_token` cookie
+#[derive(Debug)]
+pub struct VoterToken(pub Option<String>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for VoterToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let header = parts
+            .headers
+            .get(VOTER_TOKEN_HEADER)
+            .and_then(|x| x.to_str().ok());
+        let cookie = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(';'))
+            .filter_map(|x| x.trim().split_once('='))
+            .find(|(name, _)| *name == VOTER_TOKEN_COOKIE)
+            .map(|(_, value)| value);
+
+        Ok(VoterToken(header.or(cookie).and_then(|x| {
+            signing::verify_voter_token(state.server_secret.as_bytes(), x)
+        })))
+    }
+}
+
+// Hands a new token to browsers as a cookie and to other clients as a header
+fn voter_token_headers(token: String) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let cookie = format!(
+        "{}={}; Path=/; Max-Age=31536000; HttpOnly; Secure; SameSite=Lax",
+        VOTER_TOKEN_COOKIE, token
+    );
+    headers.insert(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&cookie).expect("hex token"),
+    );
+    headers.insert(
+        VOTER_TOKEN_HEADER,
+        HeaderValue::from_str(&token).expect("hex token"),
+    );
+    headers
+}
+
+#[derive(Deserialize)]
+pub struct RatingsGetOptionsExtractor {
+    // Adds derived statistics per format to every card
+    #[serde(default)]
+    stats: bool,
+    // Orders cards by their score in `rank_format`, the first enabled format if not given
+    rank: Option<RankMethod>,
+    rank_format: Option<FormatId>,
+    // Adds name, rarity, colors and the other attributes of every card
+    #[serde(default)]
+    cards: bool,
+}
+
+// Card filters of `GET /ratings`, cards without recorded attributes only pass `min_votes`
+#[derive(Deserialize, Debug)]
+pub struct RatingsFilterExtractor {
+    // Comma separated, e.g. `rare,mythic`
+    rarity: Option<String>,
+    // Cards with at least these colors, e.g. `WU`, or `C` for colorless
+    color: Option<String>,
+    #[serde(rename = "type")]
+    type_line: Option<String>,
+    mv_min: Option<f64>,
+    mv_max: Option<f64>,
+    min_votes: Option<i64>,
+}
+
+static RARITIES: &[&str] = &["common", "uncommon", "rare", "mythic", "special", "bonus"];
+
+impl RatingsFilterExtractor {
+    fn into_filter(self) -> Result<RatingsFilter, ApiError> {
+        let rarities = self
+            .rarity
+            .map(|x| {
+                x.split(',')
+                    .map(|r| match r.trim().to_lowercase() {
+                        r if RARITIES.contains(&r.as_str()) => Ok(r),
+                        r => Err(ApiError::InvalidRequest(format!("Unknown rarity '{}'", r))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        let colors = self
+            .color
+            .map(|x| util::parse_color_filter(&x))
+            .transpose()
+            .map_err(ApiError::InvalidRequest)?;
+
+        Ok(RatingsFilter {
+            rarities,
+            colors,
+            type_line: self.type_line.filter(|x| !x.is_empty()),
+            mana_value_min: self.mv_min,
+            mana_value_max: self.mv_max,
+            min_votes: self.min_votes,
+            format_id: None,
+        })
+    }
 }
 
 #[derive(Serialize)]
@@ -49,23 +216,88 @@ struct CardGetResponse {
     set_code: String,
     card_code: String,
     rating_by_format: HashMap<String, SchemaRatings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats_by_format: Option<HashMap<String, RatingStats>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rank: Option<RankScore>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    card: Option<SchemaCard>,
 }
 
 #[derive(Serialize)]
 pub struct RatingsGetResponse {
-    collection_id: String,
+    collection_id: CollectionId,
     collection_info: Collection,
     ratings: Vec<CardGetResponse>,
 }
 
-fn parse_rating(rating_raw: &String) -> Result<RatingsValue, anyhow::Error> {
-    match rating_raw.as_str() {
+#[derive(Serialize)]
+struct CollectionCardGetResponse {
+    collection_id: String,
+    rating_by_format: HashMap<String, SchemaRatings>,
+}
+
+#[derive(Serialize)]
+pub struct CardRatingsGetResponse {
+    set_code: SetCode,
+    card_code: CardCode,
+    collections: Vec<CollectionCardGetResponse>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RatingsSummaryExtractor {
+    format_id: FormatId,
+}
+
+#[derive(Serialize)]
+pub struct RatingsSummaryGetResponse {
+    collection_id: CollectionId,
+    format_id: FormatId,
+    #[serde(flatten)]
+    summary: Summary,
+}
+
+#[derive(Deserialize)]
+pub struct RatingsHistoryExtractor {
+    set_code: Option<SetCode>,
+    card_code: Option<CardCode>,
+    format_id: Option<FormatId>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct RatingsHistoryPoint {
+    #[serde(flatten)]
+    snapshot: SchemaSnapshot,
+    votes: i64,
+    mean: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct RatingsHistoryGetResponse {
+    collection_id: CollectionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    set_code: Option<SetCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    card_code: Option<CardCode>,
+    series: Vec<RatingsHistoryPoint>,
+}
+
+#[derive(Deserialize)]
+pub struct RatingsExportExtractor {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+fn parse_rating(rating_raw: &str) -> Result<RatingsValue, ApiError> {
+    match rating_raw {
         "1" => Ok(RatingsValue::Rated1),
         "2" => Ok(RatingsValue::Rated2),
         "3" => Ok(RatingsValue::Rated3),
         "4" => Ok(RatingsValue::Rated4),
         "5" => Ok(RatingsValue::Rated5),
-        _ => Err(anyhow!("bad post rating")),
+        x => Err(ApiError::InvalidRating(x.to_owned())),
     }
 }
 
@@ -76,7 +308,6 @@ fn card_code_under_1000ish(card_code: &str) -> bool {
         // Some card_codes (scryfall term `collector_number` are non-numerical due to formats like A-<num> or <num>* (star emoji))
         let filtered = card_code
             .split("")
-            .into_iter()
             .filter(|x| "0123456789".contains(x))
             .collect::<String>();
         if let Ok(x) = filtered.parse::<usize>() {
@@ -87,135 +318,190 @@ fn card_code_under_1000ish(card_code: &str) -> bool {
     false
 }
 
+// A fresh challenge for a vote on the collection, with difficulty 0 when votes currently need none
+#[instrument(err(Debug, level = "warn"))]
+pub async fn get_challenge(
+    State(state): State<AppState>,
+    ValidQuery(RatingsCollectionExtractor { collection_id }): ValidQuery<
+        RatingsCollectionExtractor,
+    >,
+) -> Result<Json<Challenge>, ApiError> {
+    let server_data = state.server_data.load();
+    let Some(collection) = server_data.collections.entries.get(collection_id.as_str()) else {
+        return Err(ApiError::UnknownCollection(collection_id.into_inner()));
+    };
+
+    Ok(Json(pow::issue_challenge(
+        state.server_secret.as_bytes(),
+        &collection_id,
+        pow::current_difficulty(&state, collection),
+        state.pow.challenge_ttl,
+    )))
+}
+
 #[instrument(err(Debug, level = "warn"))]
 pub async fn post_ratings(
     ip: SecureClientIp,
     State(state): State<AppState>,
-    Query(RatingsCollectionExtractor { collection_id }): Query<RatingsCollectionExtractor>,
-    Query(RatingsPostExtractor {
+    ValidQuery(RatingsCollectionExtractor { collection_id }): ValidQuery<
+        RatingsCollectionExtractor,
+    >,
+    ValidQuery(RatingsPostExtractor {
         rating: rating_raw,
         card_code,
         set_code,
         format_id,
-    }): Query<RatingsPostExtractor>,
-) -> impl IntoResponse {
-    // Cache combination of all inputs besides the actual rating to prevent ruining our data from repeated malicious POST requests
-    // Note that we either block to acquire a lock or use `try_lock()`, trading off server throughput with how much we filter
-    {
-        let cache_key = format!(
-            "{}{}{}{}{}",
-            ip.0, collection_id, set_code, card_code, format_id
-        );
-        let arc = state.post_rating_request_cache.clone();
-        let mutex = arc.lock();
-        if let Ok(mut cache) = mutex {
-            let elem = cache.get(&cache_key);
-            let res = match elem {
-                None => Some(1),
-                Some(x) if *x < state.server_data.collections.formats.len() => Some(*x + 1),
-                _ => None,
-            };
-            if let Some(x) = res {
-                cache.push(cache_key, x);
-            } else {
-                return Err((
-                    StatusCode::TOO_MANY_REQUESTS,
-                    "Please report if you saw this error during intended usage of the website."
-                        .into(),
-                ));
-            }
-        }
-    }
+    }): ValidQuery<RatingsPostExtractor>,
+    ValidQuery(RatingsChallengeExtractor {
+        challenge,
+        solution,
+    }): ValidQuery<RatingsChallengeExtractor>,
+    VoterToken(voter_id): VoterToken,
+) -> Result<impl IntoResponse, ApiError> {
+    let server_data = state.server_data.load_full();
 
-    let rating = match parse_rating(&rating_raw) {
-        Ok(x) => x,
-        Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string())),
+    // First time voters get a token, so they can change or retract the vote later on
+    let (voter_id, new_token) = match voter_id {
+        Some(x) => (x, None),
+        None => {
+            let (voter_id, token) = signing::issue_voter_token(state.server_secret.as_bytes());
+            (voter_id, Some(token))
+        }
+    };
+    let voter = Voter {
+        key: signing::voter_key(state.server_secret.as_bytes(), &ip.0),
+        subnet_key: signing::subnet_key(state.server_secret.as_bytes(), &ip.0),
+        id: Some(voter_id),
     };
 
-    let collection = match state.server_data.collections.entries.get(&collection_id) {
-        None => return Err((StatusCode::BAD_REQUEST, "Unknown collection".into())),
+    let rating = parse_rating(&rating_raw)?;
+
+    let collection = match server_data.collections.entries.get(collection_id.as_str()) {
+        None => return Err(ApiError::UnknownCollection(collection_id.into_inner())),
         Some(c) => c,
     };
 
     if collection.excluded_formats.contains(&format_id) {
-        return Err((StatusCode::BAD_REQUEST, "Excluded format supplied".into()));
+        return Err(ApiError::ExcludedFormat(format_id.into_inner()));
     }
+    if server_data
+        .collections
+        .formats
+        .iter()
+        .all(|x| x.title != *format_id)
+    {
+        return Err(ApiError::UnknownFormat(format_id.into_inner()));
+    }
+    if !collection.set_order.contains(&set_code) {
+        return Err(ApiError::SetNotInCollection(set_code.into_inner()));
+    }
+    let solved = pow::check_solution(
+        &state,
+        &collection_id,
+        collection,
+        challenge.as_deref(),
+        solution.as_deref(),
+    )?;
 
-    match lib::increment_rating(
-        &state.pool,
+    let mut tx = state.pool.begin().await?;
+    let mut rows = lib::increment_rating(
+        &mut tx,
         &rating,
         &collection_id,
         &card_code,
         &set_code,
         &format_id,
+        &voter,
     )
-    .await
-    {
-        Ok(n) if n == 0 => {
-            // This entire block aims to add a missing set/card combo due to a currently releasing set
-            if state
-                .server_data
-                .collections
-                .formats
-                .iter()
-                .all(|x| x.title != format_id)
-            {
-                return Err((StatusCode::BAD_REQUEST, "Unknown Format".into()));
-            }
+    .await?;
+    // Adds a missing set/card combo due to a currently releasing set
+    if rows == 0 && collection.releasing && card_code_under_1000ish(&card_code) {
+        info!(
+            "Adding missing entry {} {} to collection {}",
+            set_code, card_code, collection_id
+        );
+        init_db::run_ratings_query(
+            &state.pool,
+            &server_data.collections.formats,
+            &(
+                collection_id.to_string(),
+                vec![CardDetail {
+                    set: set_code.to_string(),
+                    collector_number: card_code.to_string(),
+                    ..Default::default()
+                }],
+            ),
+        )
+        .await?;
+        rows = lib::increment_rating(
+            &mut tx,
+            &rating,
+            &collection_id,
+            &card_code,
+            &set_code,
+            &format_id,
+            &voter,
+        )
+        .await?;
+    }
+    // Votes on unknown cards are dropped, and keep their challenge for the next vote
+    if rows > 0 {
+        if let Some(x) = solved {
+            pow::spend_challenge(&mut tx, &x).await?;
+        }
+        tx.commit().await?;
+    }
 
-            let collection = match state.server_data.collections.entries.get(&collection_id) {
-                None => return Err((StatusCode::BAD_REQUEST, "Unknown collection".into())),
-                Some(c) => c,
-            };
+    // We intentionally do not return an updated result
+    Ok(new_token.map(voter_token_headers).unwrap_or_default())
+}
 
-            if !collection.set_order.contains(&set_code) {
-                return Err((StatusCode::BAD_REQUEST, "Set not in collection".into()));
-            }
+// Takes back the caller's vote, identified by the voter token it was cast with
+#[instrument(err(Debug, level = "warn"))]
+pub async fn delete_ratings(
+    State(state): State<AppState>,
+    ValidQuery(RatingsCollectionExtractor { collection_id }): ValidQuery<
+        RatingsCollectionExtractor,
+    >,
+    ValidQuery(RatingsDeleteExtractor {
+        card_code,
+        set_code,
+        format_id,
+    }): ValidQuery<RatingsDeleteExtractor>,
+    VoterToken(voter_id): VoterToken,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state
+        .server_data
+        .load()
+        .collections
+        .entries
+        .contains_key(collection_id.as_str())
+    {
+        return Err(ApiError::UnknownCollection(collection_id.into_inner()));
+    }
 
-            if collection.releasing && card_code_under_1000ish(&card_code) {
-                info!(
-                    "Adding missing entry {} {} to collection {}",
-                    set_code, card_code, collection_id
-                );
-                if let Err(e) = init_db::run_ratings_query(
-                    &state.pool,
-                    &state.server_data.collections.formats,
-                    &(
-                        collection_id.clone(),
-                        vec![CardDetail {
-                            set: set_code.clone(),
-                            collector_number: card_code.clone(),
-                        }],
-                    ),
-                )
-                .await
-                {
-                    return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
-                } else {
-                    if let Err(e) = lib::increment_rating(
-                        &state.pool,
-                        &rating,
-                        &collection_id,
-                        &card_code,
-                        &set_code,
-                        &format_id,
-                    )
-                    .await
-                    {
-                        tracing::error!(
-                            "Attempt to add unkown card failed due to {}",
-                            e.to_string()
-                        );
-                    }
-                }
-            }
+    let retracted = match voter_id {
+        Some(voter_id) => {
+            lib::retract_rating(
+                &state.pool,
+                &collection_id,
+                &card_code,
+                &set_code,
+                &format_id,
+                &voter_id,
+            )
+            .await?
         }
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-        _ => (),
-    }
+        None => false,
+    };
 
-    // We intentionally do not return an updated result
-    Ok(())
+    match retracted {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(ApiError::VoteNotFound(
+            set_code.into_inner(),
+            card_code.into_inner(),
+        )),
+    }
 }
 
 fn parse_schemas(v: Vec<SchemaRatings>) -> Vec<CardGetResponse> {
@@ -235,34 +521,308 @@ fn parse_schemas(v: Vec<SchemaRatings>) -> Vec<CardGetResponse> {
                 card_code: y.card_code.clone(),
                 set_code: y.set_code.clone(),
                 rating_by_format: HashMap::from([(y.format_id.clone(), y)]),
+                stats_by_format: None,
+                rank: None,
+                card: None,
             });
             x
         })
 }
 
+fn rank_cards(cards: &mut [CardGetResponse], format_id: &str, method: RankMethod) {
+    let ranked = cards
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| c.rating_by_format.get(format_id).map(|r| (i, r.counts())))
+        .collect::<Vec<_>>();
+    let scores = stats::rank_scores(
+        &ranked.iter().map(|(_, counts)| *counts).collect::<Vec<_>>(),
+        method,
+    );
+    for ((i, _), score) in ranked.into_iter().zip(scores) {
+        cards[i].rank = Some(score);
+    }
+
+    // Cards without the format go last
+    cards.sort_by(|a, b| match (&a.rank, &b.rank) {
+        (Some(a), Some(b)) => b.score.total_cmp(&a.score),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+}
+
 #[instrument(err(Debug, level = "warn"))]
 pub async fn get_ratings(
     State(state): State<AppState>,
-    Query(RatingsCollectionExtractor { collection_id }): Query<RatingsCollectionExtractor>,
-) -> impl IntoResponse {
-    let collection = match state.server_data.collections.entries.get(&collection_id) {
+    ValidQuery(RatingsCollectionExtractor { collection_id }): ValidQuery<
+        RatingsCollectionExtractor,
+    >,
+    ValidQuery(RatingsGetOptionsExtractor {
+        stats,
+        rank,
+        rank_format,
+        cards,
+    }): ValidQuery<RatingsGetOptionsExtractor>,
+    ValidQuery(filter): ValidQuery<RatingsFilterExtractor>,
+) -> Result<impl IntoResponse, ApiError> {
+    let filter = filter.into_filter()?;
+    let server_data = state.server_data.load_full();
+    let collection = match server_data.collections.entries.get(collection_id.as_str()) {
+        Some(x) => x,
+        None => return Err(ApiError::UnknownCollection(collection_id.into_inner())),
+    };
+
+    let rank_format = match rank_format {
+        Some(x) => x.into_inner(),
+        None => server_data
+            .collections
+            .formats
+            .iter()
+            .find(|x| x.enabled && !collection.excluded_formats.contains(&x.title))
+            .map(|x| x.title.clone())
+            .unwrap_or_default(),
+    };
+    if rank.is_some()
+        && (collection.excluded_formats.contains(&rank_format)
+            || server_data
+                .collections
+                .formats
+                .iter()
+                .all(|x| x.title != rank_format))
+    {
+        return Err(ApiError::UnknownFormat(rank_format));
+    }
+
+    match lib::get_ratings(&state.pool, &collection_id, &collection.set_order, &filter).await {
+        Err(e) => Err(e.into()),
+        Ok(x) => {
+            let mut ratings = parse_schemas(x);
+            if stats {
+                for card in ratings.iter_mut() {
+                    card.stats_by_format = Some(
+                        card.rating_by_format
+                            .iter()
+                            .map(|(k, v)| (k.clone(), RatingStats::from_counts(v.counts())))
+                            .collect(),
+                    );
+                }
+            }
+            if cards {
+                let mut attributes =
+                    cards::get_collection_cards(&state.pool, &collection_id).await?;
+                for card in ratings.iter_mut() {
+                    card.card = attributes.remove(&(card.set_code.clone(), card.card_code.clone()));
+                }
+            }
+            if let Some(method) = rank {
+                rank_cards(&mut ratings, &rank_format, method);
+            }
+
+            Ok(Json(RatingsGetResponse {
+                collection_id,
+                collection_info: collection.clone(),
+                ratings,
+            }))
+        }
+    }
+}
+
+#[instrument(err(Debug, level = "warn"))]
+pub async fn get_card_ratings(
+    State(state): State<AppState>,
+    ValidPath((set_code, card_code)): ValidPath<(SetCode, CardCode)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let rows = match lib::get_card_ratings(&state.pool, &set_code, &card_code).await {
+        Err(e) => return Err(e.into()),
+        Ok(x) if x.is_empty() => {
+            return Err(ApiError::CardNotFound(
+                set_code.into_inner(),
+                card_code.into_inner(),
+            ))
+        }
+        Ok(x) => x,
+    };
+
+    // Rows are ordered by collection, so each collection is one consecutive run
+    let collections = rows
+        .into_iter()
+        .fold(Vec::<CollectionCardGetResponse>::new(), |mut x, y| {
+            if let Some(e) = x.last_mut() {
+                if e.collection_id == y.collection_id {
+                    e.rating_by_format
+                        .insert(y.ratings.format_id.clone(), y.ratings);
+                    return x;
+                }
+            }
+            x.push(CollectionCardGetResponse {
+                collection_id: y.collection_id,
+                rating_by_format: HashMap::from([(y.ratings.format_id.clone(), y.ratings)]),
+            });
+            x
+        });
+
+    Ok(Json(CardRatingsGetResponse {
+        set_code,
+        card_code,
+        collections,
+    }))
+}
+
+#[instrument(err(Debug, level = "warn"))]
+pub async fn get_ratings_summary(
+    State(state): State<AppState>,
+    ValidQuery(RatingsCollectionExtractor { collection_id }): ValidQuery<
+        RatingsCollectionExtractor,
+    >,
+    ValidQuery(RatingsSummaryExtractor { format_id }): ValidQuery<RatingsSummaryExtractor>,
+) -> Result<impl IntoResponse, ApiError> {
+    let server_data = state.server_data.load_full();
+    let collection = match server_data.collections.entries.get(collection_id.as_str()) {
         Some(x) => x,
-        None => return Err((StatusCode::BAD_REQUEST, collection_id)),
+        None => return Err(ApiError::UnknownCollection(collection_id.into_inner())),
+    };
+    if collection.excluded_formats.contains(&format_id)
+        || server_data
+            .collections
+            .formats
+            .iter()
+            .all(|x| x.title != *format_id)
+    {
+        return Err(ApiError::UnknownFormat(format_id.into_inner()));
+    }
+
+    let ratings = lib::get_ratings(
+        &state.pool,
+        &collection_id,
+        &collection.set_order,
+        &RatingsFilter {
+            format_id: Some(format_id.to_string()),
+            ..RatingsFilter::default()
+        },
+    )
+    .await?;
+    let attributes = cards::get_collection_cards(&state.pool, &collection_id).await?;
+
+    let summary = summary::summarize(ratings.iter().map(|x| {
+        (
+            attributes.get(&(x.set_code.clone(), x.card_code.clone())),
+            x.counts(),
+        )
+    }));
+
+    Ok(Json(RatingsSummaryGetResponse {
+        collection_id,
+        format_id,
+        summary,
+    }))
+}
+
+#[instrument(err(Debug, level = "warn"))]
+pub async fn get_ratings_history(
+    State(state): State<AppState>,
+    ValidQuery(RatingsCollectionExtractor { collection_id }): ValidQuery<
+        RatingsCollectionExtractor,
+    >,
+    ValidQuery(RatingsHistoryExtractor {
+        set_code,
+        card_code,
+        format_id,
+        from,
+        to,
+    }): ValidQuery<RatingsHistoryExtractor>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state
+        .server_data
+        .load()
+        .collections
+        .entries
+        .contains_key(collection_id.as_str())
+    {
+        return Err(ApiError::UnknownCollection(collection_id.into_inner()));
+    }
+
+    let card = match (&set_code, &card_code) {
+        (Some(s), Some(c)) => Some((s.as_str(), c.as_str())),
+        (None, None) => None,
+        _ => {
+            return Err(ApiError::InvalidRequest(
+                "set_code and card_code go together".into(),
+            ))
+        }
     };
 
-    match lib::get_ratings(&state.pool, &collection_id, &collection.set_order).await {
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-        Ok(x) => Ok(Json(RatingsGetResponse {
+    match snapshots::get_ratings_history(
+        &state.pool,
+        &collection_id,
+        card,
+        format_id.as_deref().map(String::as_str),
+        from,
+        to,
+    )
+    .await
+    {
+        Err(e) => Err(e.into()),
+        Ok(x) => Ok(Json(RatingsHistoryGetResponse {
             collection_id,
-            collection_info: collection.clone(),
-            ratings: parse_schemas(x),
+            set_code,
+            card_code,
+            series: x
+                .into_iter()
+                .map(|s| {
+                    let stats = RatingStats::from_counts(s.counts());
+                    RatingsHistoryPoint {
+                        snapshot: s,
+                        votes: stats.votes,
+                        mean: stats.mean,
+                    }
+                })
+                .collect(),
         })),
     }
 }
 
+#[instrument(err(Debug, level = "warn"))]
+pub async fn export_ratings(
+    State(state): State<AppState>,
+    ValidQuery(RatingsCollectionExtractor { collection_id }): ValidQuery<
+        RatingsCollectionExtractor,
+    >,
+    ValidQuery(RatingsExportExtractor { format }): ValidQuery<RatingsExportExtractor>,
+) -> Result<impl IntoResponse, ApiError> {
+    let set_order = match state
+        .server_data
+        .load()
+        .collections
+        .entries
+        .get(collection_id.as_str())
+    {
+        Some(x) => x.set_order.clone(),
+        None => return Err(ApiError::UnknownCollection(collection_id.into_inner())),
+    };
+
+    let disposition = format!(
+        "attachment; filename=\"{}.{}\"",
+        collection_id,
+        format.extension()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        export::stream_ratings(
+            state.pool.clone(),
+            collection_id.into_inner(),
+            set_order,
+            format,
+        ),
+    ))
+}
+
 #[instrument(err(Debug))]
 pub async fn get_collections(
     State(state): State<AppState>,
-) -> Result<Json<crate::util::CollectionsJson>, axum::response::ErrorResponse> {
-    return Ok(Json(state.server_data.collections));
+) -> Result<Json<crate::util::CollectionsJson>, ApiError> {
+    return Ok(Json(state.server_data.load().collections.clone()));
 }
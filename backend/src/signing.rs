@@ -0,0 +1,107 @@
+use std::net::IpAddr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::net;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Hex encoded HMAC-SHA256 of `data`, keyed with the server secret.
+pub fn hmac_hex(secret: &[u8], data: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Stable, anonymised identifier for a voter.
+/// Without the secret the raw ip cannot be recovered from it, but repeated votes from one client still line up.
+pub fn voter_key(secret: &[u8], ip: &IpAddr) -> String {
+    hmac_hex(secret, ip.to_string().as_bytes())
+}
+
+// Like `voter_key`, but shared by every address of the client's /24 (IPv4) or /64 (IPv6) network
+pub fn subnet_key(secret: &[u8], ip: &IpAddr) -> String {
+    let network = net::client_network(ip, 24);
+    hmac_hex(secret, format!("subnet:{}", network).as_bytes())
+}
+
+// Random voter id plus its signature, e.g. `<32 hex chars>.<64 hex chars>`
+// Only the id is stored with the votes, the signature keeps clients from picking someone else's
+pub fn issue_voter_token(secret: &[u8]) -> (String, String) {
+    let voter_id = hex::encode(rand::random::<[u8; 16]>());
+    let token = format!("{}.{}", voter_id, voter_token_signature(secret, &voter_id));
+    (voter_id, token)
+}
+
+// The voter id of a token signed with `secret`
+pub fn verify_voter_token(secret: &[u8], token: &str) -> Option<String> {
+    let (voter_id, signature) = token.split_once('.')?;
+    if voter_id.len() != 32 || !voter_id.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    constant_time_eq(
+        voter_token_signature(secret, voter_id).as_bytes(),
+        signature.as_bytes(),
+    )
+    .then(|| voter_id.to_owned())
+}
+
+fn voter_token_signature(secret: &[u8], voter_id: &str) -> String {
+    // Prefixed so a token signature can never double as a voter key
+    hmac_hex(secret, format!("voter-token:{}", voter_id).as_bytes())
+}
+
+// Compares secrets without leaking the position of the first mismatch through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voter_key() {
+        let ip: IpAddr = "192.168.0.1".parse().unwrap();
+        let key = voter_key(b"secret", &ip);
+
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, voter_key(b"secret", &ip));
+        assert_ne!(key, voter_key(b"other secret", &ip));
+        assert_ne!(key, voter_key(b"secret", &"192.168.0.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_subnet_key() {
+        let key = |x: &str| subnet_key(b"secret", &x.parse().unwrap());
+
+        assert_eq!(key("192.168.0.1"), key("192.168.0.254"));
+        assert_ne!(key("192.168.0.1"), key("192.168.1.1"));
+        assert_ne!(
+            key("192.168.0.1"),
+            voter_key(b"secret", &"192.168.0.1".parse().unwrap())
+        );
+        assert_eq!(key("2001:db8:0:1::1"), key("2001:db8:0:1:ffff::2"));
+        assert_ne!(key("2001:db8:0:1::1"), key("2001:db8:0:2::1"));
+        assert_eq!(key("::ffff:192.168.0.1"), key("192.168.0.254"));
+    }
+
+    #[test]
+    fn test_voter_token() {
+        let (voter_id, token) = issue_voter_token(b"secret");
+
+        assert_eq!(
+            verify_voter_token(b"secret", &token),
+            Some(voter_id.clone())
+        );
+        assert_eq!(verify_voter_token(b"other secret", &token), None);
+        assert_eq!(verify_voter_token(b"secret", &voter_id), None);
+        let other_id = if token.starts_with('0') { "1" } else { "0" };
+        assert_eq!(
+            verify_voter_token(b"secret", &format!("{}{}", other_id, &token[1..])),
+            None
+        );
+        assert_ne!(issue_voter_token(b"secret").0, voter_id);
+    }
+}
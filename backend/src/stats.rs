@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+
+// Standard deviation of a 1-5 rating split evenly between 1 and 5, the most divided a card can be
+const MAX_STD_DEV: f64 = 2.0;
+
+// Two-sided 95% quantile of the standard normal distribution
+const Z_95: f64 = 1.959964;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RatingStats {
+    pub votes: i64,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    // Ties go to the lower rating
+    pub mode: Option<u8>,
+    pub std_dev: Option<f64>,
+    // `std_dev` scaled to [0, 1], 0 if everyone agrees and 1 if votes are split between 1 and 5
+    pub polarization: Option<f64>,
+}
+
+// Rating of the vote at `index` when all votes are sorted ascending
+fn nth_rating(counts: &[i64; 5], index: i64) -> f64 {
+    let mut seen = 0;
+    for (i, count) in counts.iter().enumerate() {
+        seen += count;
+        if index < seen {
+            return (i + 1) as f64;
+        }
+    }
+
+    counts.len() as f64
+}
+
+impl RatingStats {
+    // `counts[i]` is the number of votes for rating `i + 1`
+    pub fn from_counts(counts: [i64; 5]) -> RatingStats {
+        let votes = counts.iter().sum::<i64>();
+        if votes <= 0 {
+            return RatingStats {
+                votes: 0,
+                mean: None,
+                median: None,
+                mode: None,
+                std_dev: None,
+                polarization: None,
+            };
+        }
+
+        let n = votes as f64;
+        let mean = counts
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i + 1) as f64 * *c as f64)
+            .sum::<f64>()
+            / n;
+        let variance = counts
+            .iter()
+            .enumerate()
+            .map(|(i, c)| ((i + 1) as f64 - mean).powi(2) * *c as f64)
+            .sum::<f64>()
+            / n;
+        let std_dev = variance.sqrt();
+        let median = (nth_rating(&counts, (votes - 1) / 2) + nth_rating(&counts, votes / 2)) / 2.0;
+        let mode = counts
+            .iter()
+            .enumerate()
+            .fold(
+                (0, 0),
+                |best, (i, c)| if *c > best.1 { (i, *c) } else { best },
+            )
+            .0 as u8
+            + 1;
+
+        RatingStats {
+            votes,
+            mean: Some(mean),
+            median: Some(median),
+            mode: Some(mode),
+            std_dev: Some(std_dev),
+            polarization: Some(std_dev / MAX_STD_DEV),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RankMethod {
+    // Mean pulled towards the collection/format mean, cards with few votes stay close to it
+    Bayesian,
+    // Lower end of the 95% interval, a card needs many consistent votes to rank high
+    LowerBound,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RankScore {
+    // Value cards are ordered by, one of the fields below depending on the `RankMethod`
+    pub score: f64,
+    pub bayesian_mean: f64,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+}
+
+fn sum_and_votes(counts: &[i64; 5]) -> (f64, f64) {
+    counts
+        .iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(sum, votes), (i, c)| {
+            (sum + (i + 1) as f64 * *c as f64, votes + *c as f64)
+        })
+}
+
+fn bayesian_mean(counts: &[i64; 5], prior_mean: f64, prior_weight: f64) -> f64 {
+    let (sum, votes) = sum_and_votes(counts);
+    (prior_mean * prior_weight + sum) / (prior_weight + votes)
+}
+
+// 95% interval of the mean score, using a uniform Dirichlet prior over the five buckets
+// Same idea as a Wilson interval, but for five outcomes instead of two
+fn dirichlet_interval(counts: &[i64; 5]) -> (f64, f64) {
+    let alphas = counts.map(|c| c as f64 + 1.0);
+    let total = alphas.iter().sum::<f64>();
+    let (mean, mean_sq) = alphas
+        .iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(m, sq), (i, a)| {
+            let rating = (i + 1) as f64;
+            (m + rating * a / total, sq + rating * rating * a / total)
+        });
+    let deviation = ((mean_sq - mean * mean) / (total + 1.0)).sqrt();
+
+    (mean - Z_95 * deviation, mean + Z_95 * deviation)
+}
+
+// Scores every card in `counts`, which should hold all cards of one collection and format
+// The bayesian prior is their vote weighted mean, weighted like the average number of votes a rated card has
+pub fn rank_scores(counts: &[[i64; 5]], method: RankMethod) -> Vec<RankScore> {
+    let (sum, votes) = counts
+        .iter()
+        .map(sum_and_votes)
+        .fold((0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+    let rated_cards = counts.iter().filter(|c| c.iter().sum::<i64>() > 0).count();
+    let prior_mean = if votes > 0.0 { sum / votes } else { 3.0 };
+    let prior_weight = if rated_cards > 0 {
+        (votes / rated_cards as f64).max(1.0)
+    } else {
+        1.0
+    };
+
+    counts
+        .iter()
+        .map(|c| {
+            let bayesian_mean = bayesian_mean(c, prior_mean, prior_weight);
+            let (lower_bound, upper_bound) = dirichlet_interval(c);
+            RankScore {
+                score: match method {
+                    RankMethod::Bayesian => bayesian_mean,
+                    RankMethod::LowerBound => lower_bound,
+                },
+                bayesian_mean,
+                lower_bound,
+                upper_bound,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_counts() {
+        assert_eq!(RatingStats::from_counts([0; 5]).votes, 0);
+        assert_eq!(RatingStats::from_counts([0; 5]).mean, None);
+
+        let stats = RatingStats::from_counts([1, 0, 2, 0, 1]);
+        assert_eq!(stats.votes, 4);
+        assert_eq!(stats.mean, Some(3.0));
+        assert_eq!(stats.median, Some(3.0));
+        assert_eq!(stats.mode, Some(3));
+        assert_eq!(stats.std_dev, Some(2f64.sqrt()));
+
+        let split = RatingStats::from_counts([3, 0, 0, 0, 3]);
+        assert_eq!(split.median, Some(3.0));
+        assert_eq!(split.mode, Some(1));
+        assert_eq!(split.polarization, Some(1.0));
+
+        let agreed = RatingStats::from_counts([0, 0, 0, 7, 0]);
+        assert_eq!(agreed.median, Some(4.0));
+        assert_eq!(agreed.polarization, Some(0.0));
+    }
+
+    #[test]
+    fn test_rank_scores() {
+        // Two perfect votes against hundreds of votes averaging 4.6
+        let counts = [
+            [0, 0, 0, 0, 2],
+            [0, 0, 20, 40, 440],
+            [50, 100, 200, 100, 50],
+        ];
+
+        for method in [RankMethod::Bayesian, RankMethod::LowerBound] {
+            let scores = rank_scores(&counts, method);
+            assert!(scores[1].score > scores[0].score);
+        }
+
+        // The lower bound still distrusts two votes more than a mediocre but well established card
+        let scores = rank_scores(&counts, RankMethod::Bayesian);
+        assert!(scores[0].score > scores[2].score);
+        let scores = rank_scores(&counts, RankMethod::LowerBound);
+        assert!(scores[2].score > scores[0].score);
+        assert!(scores.iter().all(|s| s.lower_bound < s.upper_bound));
+        assert!(
+            scores[0].upper_bound - scores[0].lower_bound
+                > scores[1].upper_bound - scores[1].lower_bound
+        );
+
+        let unrated = rank_scores(&[[0; 5]], RankMethod::Bayesian);
+        assert_eq!(unrated[0].score, 3.0);
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{db::cards::SchemaCard, stats::RatingStats};
+
+static COLORS: &[&str] = &["W", "U", "B", "R", "G"];
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct GroupSummary {
+    pub cards: i64,
+    pub votes: i64,
+    // Mean over all votes of the group, busy cards weigh more
+    pub mean: Option<f64>,
+    // Mean of the per card means, every card with votes weighs the same
+    pub card_mean: Option<f64>,
+    #[serde(skip)]
+    counts: [i64; 5],
+    #[serde(skip)]
+    card_means: Vec<f64>,
+}
+
+impl GroupSummary {
+    fn add(&mut self, counts: [i64; 5]) {
+        self.cards += 1;
+        for (total, count) in self.counts.iter_mut().zip(counts) {
+            *total += count;
+        }
+        if let Some(mean) = RatingStats::from_counts(counts).mean {
+            self.card_means.push(mean);
+        }
+    }
+
+    fn finish(&mut self) {
+        let stats = RatingStats::from_counts(self.counts);
+        self.votes = stats.votes;
+        self.mean = stats.mean;
+        self.card_mean = match self.card_means.len() {
+            0 => None,
+            n => Some(self.card_means.iter().sum::<f64>() / n as f64),
+        };
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub total: GroupSummary,
+    pub by_rarity: BTreeMap<String, GroupSummary>,
+    // Multicolored cards count towards each of their colors, `C` is colorless
+    pub by_color: BTreeMap<String, GroupSummary>,
+    // Two color archetypes, e.g. `WU` has the white, blue and white-blue cards
+    pub by_color_pair: BTreeMap<String, GroupSummary>,
+    // Cards whose attributes were never captured, only part of `total`
+    pub cards_without_attributes: i64,
+}
+
+fn color_pairs() -> Vec<String> {
+    COLORS
+        .iter()
+        .enumerate()
+        .flat_map(|(i, a)| COLORS[i + 1..].iter().map(move |b| format!("{}{}", a, b)))
+        .collect()
+}
+
+// Rolls the votes of single format up into groups, `cards` pairs each card's counts with its attributes
+pub fn summarize<'a>(cards: impl Iterator<Item = (Option<&'a SchemaCard>, [i64; 5])>) -> Summary {
+    let pairs = color_pairs();
+    let mut summary = Summary::default();
+    for (card, counts) in cards {
+        summary.total.add(counts);
+        let Some(card) = card else {
+            summary.cards_without_attributes += 1;
+            continue;
+        };
+
+        summary
+            .by_rarity
+            .entry(card.rarity.clone())
+            .or_default()
+            .add(counts);
+        if card.colors.is_empty() {
+            summary.by_color.entry("C".into()).or_default().add(counts);
+            continue;
+        }
+        for color in card.colors.iter() {
+            summary
+                .by_color
+                .entry(color.clone())
+                .or_default()
+                .add(counts);
+        }
+        for pair in pairs.iter() {
+            if card.colors.iter().all(|x| pair.contains(x.as_str())) {
+                summary
+                    .by_color_pair
+                    .entry(pair.clone())
+                    .or_default()
+                    .add(counts);
+            }
+        }
+    }
+
+    summary.total.finish();
+    summary
+        .by_rarity
+        .values_mut()
+        .chain(summary.by_color.values_mut())
+        .chain(summary.by_color_pair.values_mut())
+        .for_each(GroupSummary::finish);
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(rarity: &str, colors: &[&str]) -> SchemaCard {
+        SchemaCard {
+            set_code: "mh3".into(),
+            card_code: "1".into(),
+            name: "card".into(),
+            rarity: rarity.into(),
+            colors: colors.iter().map(|x| x.to_string()).collect(),
+            color_identity: vec![],
+            type_line: "Creature".into(),
+            mana_value: 2.0,
+            layout: "normal".into(),
+            image_uris: None,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let (white, azorius, colorless) = (
+            card("common", &["W"]),
+            card("uncommon", &["W", "U"]),
+            card("common", &[]),
+        );
+        let summary = summarize(
+            [
+                (Some(&white), [0, 0, 0, 0, 1]),
+                (Some(&azorius), [3, 0, 0, 0, 0]),
+                (Some(&colorless), [0, 0, 0, 0, 0]),
+                (None, [0, 0, 2, 0, 0]),
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(summary.total.cards, 4);
+        assert_eq!(summary.total.votes, 6);
+        assert_eq!(summary.cards_without_attributes, 1);
+        assert_eq!(summary.by_rarity["common"].cards, 2);
+        assert_eq!(summary.by_rarity["common"].mean, Some(5.0));
+        assert_eq!(summary.by_color["C"].mean, None);
+        assert_eq!(summary.by_color["W"].mean, Some(2.0));
+        assert_eq!(summary.by_color["W"].card_mean, Some(3.0));
+        assert_eq!(summary.by_color_pair["WU"].cards, 2);
+        assert_eq!(summary.by_color_pair["WB"].cards, 1);
+        assert!(!summary.by_color_pair.contains_key("UB"));
+    }
+}
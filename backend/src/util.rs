@@ -1,38 +1,106 @@
-use anyhow::Error;
-use core::time;
-use reqwest;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::{
-    collections::{HashMap, HashSet},
-    thread,
+use std::collections::HashMap;
+
+use crate::{
+    pow,
+    validation::{CollectionId, FormatId, SetCode},
 };
-use tracing::info;
 
-#[derive(Hash, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(PartialEq, Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "ScryfallCard")]
 pub struct CardDetail {
     pub set: String,
     pub collector_number: String,
+    pub name: String,
+    pub rarity: String,
+    pub colors: Vec<String>,
+    pub color_identity: Vec<String>,
+    pub type_line: String,
+    // Scryfall still calls the mana value `cmc`
+    pub cmc: f64,
+    pub layout: String,
+    pub image_uris: Option<HashMap<String, String>>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Deserialize)]
 #[serde(default)]
-pub struct Cards {
-    pub data: Vec<CardDetail>,
+struct ScryfallCardFace {
+    colors: Option<Vec<String>>,
+    image_uris: Option<HashMap<String, String>>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+// A card object as returned by scryfall, multi-faced cards keep their colors and images on the faces
+#[derive(Default, Deserialize)]
 #[serde(default)]
-pub struct SetDetail {
-    pub code: String,
-    pub name: String,
+struct ScryfallCard {
+    set: String,
+    collector_number: String,
+    name: String,
+    rarity: String,
+    colors: Option<Vec<String>>,
+    color_identity: Vec<String>,
+    type_line: String,
+    cmc: f64,
+    layout: String,
+    image_uris: Option<HashMap<String, String>>,
+    card_faces: Vec<ScryfallCardFace>,
 }
 
-#[derive(Default, Serialize, Deserialize)]
-#[serde(default)]
-pub struct Sets {
-    pub sets: Vec<SetDetail>,
+// Colors in the usual WUBRG order, e.g. `["U", "W"]` becomes `["W", "U"]`
+pub fn sort_colors(colors: &mut Vec<String>) {
+    colors.sort_by_key(|x| "WUBRG".find(x.as_str()).unwrap_or(usize::MAX));
+    colors.dedup();
+}
+
+// Parses a color filter like `WU` or `C` (colorless) into WUBRG ordered colors, an empty list for colorless
+pub fn parse_color_filter(colors: &str) -> Result<Vec<String>, String> {
+    if colors.eq_ignore_ascii_case("c") {
+        return Ok(vec![]);
+    }
+
+    let mut parsed = colors
+        .chars()
+        .map(|x| match x.to_ascii_uppercase() {
+            x @ ('W' | 'U' | 'B' | 'R' | 'G') => Ok(x.to_string()),
+            _ => Err(format!("Invalid color filter '{}'", colors)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if parsed.is_empty() {
+        return Err("Empty color filter".into());
+    }
+    sort_colors(&mut parsed);
+
+    Ok(parsed)
+}
+
+impl From<ScryfallCard> for CardDetail {
+    fn from(card: ScryfallCard) -> Self {
+        let mut colors = card.colors.unwrap_or_else(|| {
+            card.card_faces
+                .iter()
+                .flat_map(|x| x.colors.clone().unwrap_or_default())
+                .collect()
+        });
+        sort_colors(&mut colors);
+        let mut color_identity = card.color_identity;
+        sort_colors(&mut color_identity);
+        let image_uris = card
+            .image_uris
+            .or_else(|| card.card_faces.into_iter().find_map(|x| x.image_uris));
+
+        CardDetail {
+            set: card.set,
+            collector_number: card.collector_number,
+            name: card.name,
+            rarity: card.rarity,
+            colors,
+            color_identity,
+            type_line: card.type_line,
+            cmc: card.cmc,
+            layout: card.layout,
+            image_uris,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
@@ -43,6 +111,8 @@ pub struct Collection {
     pub set_order: Vec<String>,
     pub releasing: bool,
     pub excluded_formats: Vec<String>,
+    // Leading zero bits of the proof of work votes need, see `pow`
+    pub pow_difficulty: u8,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
@@ -61,43 +131,135 @@ pub struct CollectionsJson {
 
 pub type CollectionItem = (String, Vec<CardDetail>);
 
-#[tracing::instrument]
-pub async fn get_cards_from_query(
-    scryfall_query: &String,
-) -> Result<Vec<CardDetail>, anyhow::Error> {
-    let mut cards: HashSet<_> = HashSet::new();
-    let mut i = 1; // pages start at one
-    loop {
-        let query = format!(
-            "https://api.scryfall.com/cards/search?q=-is%3Adigital+{}&order=set&unique=cards&page={}",
-            scryfall_query, i
-        );
-        info!(query);
-        let card_page =
-            serde_json::from_str::<Cards>(reqwest::get(query).await?.text().await?.as_str())?;
-        if card_page.data.len() == 0 {
-            break;
-        }
-        cards.extend(card_page.data.into_iter());
-
-        thread::sleep(time::Duration::from_millis(100));
+// This file lives in the frontend, which needs it for the first load
+// The backend only uses it to seed the `collections` tables on first run, afterwards the database is the source of truth
+pub fn parse_collections() -> Result<CollectionsJson, anyhow::Error> {
+    Ok(serde_json::from_str::<CollectionsJson>(include_str!(
+        "../resources/collections.json"
+    ))?)
+}
 
-        i += 1;
+pub fn validate_collection(
+    collection_id: &str,
+    collection: &Collection,
+    formats: &[Format],
+) -> Result<(), String> {
+    CollectionId::parse(collection_id)?;
+    if collection.scryfall_query.is_empty() {
+        return Err("Missing scryfall_query".into());
+    }
+    if collection.set_order.is_empty() {
+        return Err("set_order must list at least one set code".into());
+    }
+    for set_code in collection.set_order.iter() {
+        SetCode::parse(set_code)?;
+    }
+    if let Some(x) = collection
+        .excluded_formats
+        .iter()
+        .find(|x| formats.iter().all(|f| f.title != **x))
+    {
+        return Err(format!("Unknown format '{}' in excluded_formats", x));
+    }
+    if collection.pow_difficulty > pow::MAX_DIFFICULTY {
+        return Err(format!(
+            "pow_difficulty must be at most {}",
+            pow::MAX_DIFFICULTY
+        ));
     }
 
-    Ok(cards.into_iter().collect())
+    Ok(())
 }
 
-pub async fn resolve_collection(name: &String, c: &Collection) -> Result<CollectionItem, Error> {
-    Ok((name.clone(), get_cards_from_query(&c.scryfall_query).await?))
+pub fn validate_collections(collections: &CollectionsJson) -> Vec<String> {
+    let mut errors = vec![];
+
+    for (i, format) in collections.formats.iter().enumerate() {
+        if let Err(e) = FormatId::parse(&format.title) {
+            errors.push(format!("Format '{}': {}", format.title, e));
+        }
+        if collections.formats[..i]
+            .iter()
+            .any(|x| x.title == format.title)
+        {
+            errors.push(format!("Duplicate format '{}'", format.title));
+        }
+    }
+    if !collections.entries.contains_key(&collections.latest) {
+        errors.push(format!(
+            "Unknown latest collection '{}'",
+            collections.latest
+        ));
+    }
+    for (collection_id, collection) in collections.entries.iter() {
+        if let Err(e) = validate_collection(collection_id, collection, &collections.formats) {
+            errors.push(format!("{}: {}", collection_id, e));
+        }
+    }
+
+    errors
 }
 
-// This file lives in the frontend as the single source of truth
-// It's an odd choice, but we have it available in the repo at build time since it's still within the same docker-compose
-pub fn parse_collections() -> Result<CollectionsJson, anyhow::Error> {
-    Ok(serde_json::from_str::<CollectionsJson>(include_str!(
-        "../resources/collections.json"
-    ))?)
+// Human readable list of changes between two catalogues, one line per added, removed or changed entry
+pub fn diff_collections(old: &CollectionsJson, new: &CollectionsJson) -> Vec<String> {
+    let mut diff = vec![];
+
+    if old.latest != new.latest {
+        diff.push(format!("~ latest: {} -> {}", old.latest, new.latest));
+    }
+
+    for format in new.formats.iter() {
+        match old.formats.iter().find(|x| x.title == format.title) {
+            None => diff.push(format!("+ format {}", format.title)),
+            Some(x) if x.enabled != format.enabled => diff.push(format!(
+                "~ format {}: enabled {} -> {}",
+                format.title, x.enabled, format.enabled
+            )),
+            _ => (),
+        }
+    }
+    for format in old.formats.iter() {
+        if new.formats.iter().all(|x| x.title != format.title) {
+            diff.push(format!("- format {}", format.title));
+        }
+    }
+
+    let mut collection_ids = old
+        .entries
+        .keys()
+        .chain(new.entries.keys())
+        .collect::<Vec<_>>();
+    collection_ids.sort();
+    collection_ids.dedup();
+    for collection_id in collection_ids {
+        match (
+            old.entries.get(collection_id),
+            new.entries.get(collection_id),
+        ) {
+            (None, Some(_)) => diff.push(format!("+ collection {}", collection_id)),
+            (Some(_), None) => diff.push(format!("- collection {}", collection_id)),
+            (Some(a), Some(b)) => {
+                let (a, b) = (serde_json::to_value(a), serde_json::to_value(b));
+                if let (Ok(serde_json::Value::Object(a)), Ok(serde_json::Value::Object(b))) = (a, b)
+                {
+                    for (field, value) in b.iter() {
+                        if a.get(field) != Some(value) {
+                            diff.push(format!(
+                                "~ collection {}: {} {} -> {}",
+                                collection_id,
+                                field,
+                                a.get(field).unwrap_or(&serde_json::Value::Null),
+                                value
+                            ));
+                        }
+                    }
+                }
+            }
+            (None, None) => (),
+        }
+    }
+
+    diff
 }
 
 #[cfg(test)]
@@ -109,11 +271,61 @@ mod tests {
     fn test_parse_collections() {
         let card_list = parse_collections().unwrap();
         assert!(card_list.entries.contains_key("mh2"));
-        assert!(card_list.entries.contains_key("draft_otj"));
+        assert!(card_list.entries.contains_key("otj"));
+
+        assert_eq!(
+            card_list.entries["otj"].scryfall_query,
+            "set%3Aotj+or+set%3Aotp+or+set%3Abig+or+(e%3Aspg+cn≥29+cn≤38)"
+        );
+        assert!(validate_collections(&card_list).is_empty());
+    }
+
+    #[test]
+    fn test_diff_collections() {
+        let old = parse_collections().unwrap();
+        let mut new = old.clone();
+        assert!(diff_collections(&old, &new).is_empty());
+
+        new.entries.remove("neo");
+        new.entries.get_mut("mh3").unwrap().releasing = false;
+        new.formats[1].enabled = true;
+        assert_eq!(
+            diff_collections(&old, &new),
+            vec![
+                "~ format commander: enabled false -> true",
+                "~ collection mh3: releasing true -> false",
+                "- collection neo",
+            ]
+        );
 
+        new.latest = "neo".into();
         assert_eq!(
-            card_list.entries["draft_otj"].scryfall_query,
-            "set%3Aotp+or+set%3Abig+or+(e%3Aspg+cn≥29+cn≤38)"
+            validate_collections(&new),
+            vec!["Unknown latest collection 'neo'"]
         );
     }
+
+    #[test]
+    fn test_card_detail_from_faces() {
+        let card = serde_json::from_str::<CardDetail>(
+            r#"{"set": "mh3", "collector_number": "242", "name": "Ajani, Nacatl Pariah // Ajani, Nacatl Avenger",
+            "rarity": "mythic", "color_identity": ["W", "R"], "cmc": 2.0, "layout": "transform",
+            "card_faces": [
+                {"colors": ["W"], "image_uris": {"normal": "front.jpg"}},
+                {"colors": ["R", "W"], "image_uris": {"normal": "back.jpg"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(card.colors, vec!["W", "R"]);
+        assert_eq!(card.color_identity, vec!["W", "R"]);
+        assert_eq!(card.image_uris.unwrap()["normal"], "front.jpg");
+    }
+
+    #[test]
+    fn test_parse_color_filter() {
+        assert_eq!(parse_color_filter("uw").unwrap(), vec!["W", "U"]);
+        assert_eq!(parse_color_filter("C").unwrap(), Vec::<String>::new());
+        assert!(parse_color_filter("WC").is_err());
+        assert!(parse_color_filter("").is_err());
+    }
 }
@@ -0,0 +1,166 @@
+use std::{fmt, ops::Deref};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Path, Query, Request},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::ApiError;
+
+// Every key column of `ratings` is a `varchar(16)`
+pub const MAX_KEY_LENGTH: usize = 16;
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+// Collector numbers come with stars, daggers, letters and dashes, e.g. `123★` or `A-12`
+fn is_collector_number_char(c: char) -> bool {
+    !c.is_whitespace() && !c.is_control() && c != '/'
+}
+
+fn validate_key(kind: &str, value: &str, allowed: fn(char) -> bool) -> Result<(), String> {
+    if value.is_empty() || value.chars().count() > MAX_KEY_LENGTH {
+        return Err(format!(
+            "{} must be between 1 and {} characters",
+            kind, MAX_KEY_LENGTH
+        ));
+    }
+    if let Some(c) = value.chars().find(|c| !allowed(*c)) {
+        return Err(format!("{} contains invalid character {:?}", kind, c));
+    }
+
+    Ok(())
+}
+
+macro_rules! key_type {
+    ($(#[$meta:meta])* $name:ident, $kind:literal, $allowed:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(try_from = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn parse(value: &str) -> Result<Self, String> {
+                validate_key($kind, value, $allowed)?;
+                Ok($name(value.to_owned()))
+            }
+
+            pub fn into_inner(self) -> String {
+                self.0
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = String;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                validate_key($kind, &value, $allowed)?;
+                Ok($name(value))
+            }
+        }
+
+        impl Deref for $name {
+            type Target = String;
+
+            fn deref(&self) -> &String {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+key_type!(CollectionId, "collection_id", is_identifier_char);
+key_type!(FormatId, "format_id", is_identifier_char);
+key_type!(
+    // Scryfall set code, e.g. `mh3`
+    SetCode,
+    "set_code",
+    is_identifier_char
+);
+key_type!(
+    // Scryfall collector number within a set
+    CardCode,
+    "card_code",
+    is_collector_number_char
+);
+
+// `Query`, `Path` and `Json` with their rejections turned into `ApiError`
+// Malformed input is a 400, well formed JSON of the wrong shape keeps axum's 422
+#[derive(Debug)]
+pub struct ValidQuery<T>(pub T);
+
+#[derive(Debug)]
+pub struct ValidPath<T>(pub T);
+
+#[derive(Debug)]
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for ValidQuery<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Query::<T>::from_request_parts(parts, state).await {
+            Ok(Query(x)) => Ok(ValidQuery(x)),
+            Err(e) => Err(ApiError::InvalidRequest(e.body_text())),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: DeserializeOwned + Send, S: Send + Sync> FromRequestParts<S> for ValidPath<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(x)) => Ok(ValidPath(x)),
+            Err(e) => Err(ApiError::InvalidRequest(e.body_text())),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for ValidJson<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(request, state).await {
+            Ok(Json(x)) => Ok(ValidJson(x)),
+            Err(e) if e.status() == StatusCode::UNPROCESSABLE_ENTITY => {
+                Err(ApiError::InvalidBody(e.body_text()))
+            }
+            Err(e) => Err(ApiError::InvalidRequest(e.body_text())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        assert!(CollectionId::parse("mh3").is_ok());
+        assert!(CollectionId::parse("").is_err());
+        assert!(CollectionId::parse("a_very_long_collection").is_err());
+        assert!(FormatId::parse("limited; --").is_err());
+        assert!(CardCode::parse("123★").is_ok());
+        assert!(CardCode::parse("A-12").is_ok());
+        assert!(CardCode::parse("1 2").is_err());
+        assert_eq!(
+            serde_json::from_str::<SetCode>("\"otj\"").unwrap().as_str(),
+            "otj"
+        );
+        assert!(serde_json::from_str::<SetCode>("\"o'tj\"").is_err());
+    }
+}
@@ -0,0 +1,1162 @@
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Extension, Router,
+};
+use axum_client_ip::SecureClientIpSource;
+use backend::{
+    card_source::FixtureSource,
+    db::{
+        collections, init_db,
+        lib::{self, RatingsFilter},
+        migrations,
+        quarantine::{self, AnomalyThresholds},
+        snapshots,
+    },
+    pow::{self, PowSettings},
+    rate_limit::{Limit, MemoryStore, PostgresStore, RateLimiter, RateLimits},
+    server::AppState,
+    util::{CardDetail, Collection, CollectionsJson, Format},
+    ServerData,
+};
+use serde_json::Value;
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+mod common;
+
+use common::TestDb;
+
+fn catalogue() -> CollectionsJson {
+    let format = |title: &str| Format {
+        title: title.into(),
+        enabled: true,
+    };
+    let collection = |set: &str, releasing: bool, excluded_formats: &[&str]| Collection {
+        title: set.to_uppercase(),
+        scryfall_query: format!("set%3A{}", set),
+        set_order: vec![set.into()],
+        releasing,
+        excluded_formats: excluded_formats.iter().map(|x| x.to_string()).collect(),
+        pow_difficulty: 0,
+    };
+
+    CollectionsJson {
+        latest: "tst".into(),
+        formats: vec![format("limited"), format("cube")],
+        entries: HashMap::from([
+            ("tst".into(), collection("tst", false, &["cube"])),
+            ("new".into(), collection("new", true, &[])),
+        ]),
+    }
+}
+
+// Migrates and seeds the database like a fresh deployment, with cards from `tests/fixtures`
+async fn setup(pool: &PgPool) -> AppState {
+    migrations::migrate_up(pool, None).await.unwrap();
+    collections::seed_collections(pool, &catalogue())
+        .await
+        .unwrap();
+    let server_data = ServerData {
+        collections: collections::load_collections(pool).await.unwrap(),
+    };
+    let source = Arc::new(FixtureSource(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures"),
+    ));
+    init_db::init_db(pool, source.as_ref(), &server_data)
+        .await
+        .unwrap();
+
+    AppState::new(
+        pool.clone(),
+        server_data,
+        "secret",
+        Some("admin"),
+        source,
+        RateLimiter::default(),
+    )
+}
+
+// The router as seen from one client address, like `into_make_service_with_connect_info` provides it
+fn client(state: &AppState, ip: [u8; 4]) -> Router {
+    backend::router(state.clone(), SecureClientIpSource::ConnectInfo)
+        .layer(Extension(ConnectInfo(SocketAddr::from((ip, 1234)))))
+}
+
+async fn send(app: &Router, method: Method, uri: &str) -> (StatusCode, Vec<u8>) {
+    let (status, _, body) = send_with_headers(app, method, uri, &[]).await;
+    (status, body)
+}
+
+async fn send_with_headers(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    headers: &[(&str, &str)],
+) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let mut request = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let (status, headers) = (response.status(), response.headers().clone());
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (status, headers, body.to_vec())
+}
+
+// An admin request with a JSON body, returning the JSON response
+async fn admin(app: &Router, method: Method, uri: &str, body: Value) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, "Bearer admin")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
+
+async fn rate(
+    app: &Router,
+    collection_id: &str,
+    card: (&str, &str),
+    format_id: &str,
+    rating: u8,
+) -> StatusCode {
+    send(
+        app,
+        Method::POST,
+        &format!(
+            "/ratings?collection_id={}&set_code={}&card_code={}&format_id={}&rating={}",
+            collection_id, card.0, card.1, format_id, rating
+        ),
+    )
+    .await
+    .0
+}
+
+// Vote counts of a card in a format as returned by `GET /ratings`
+async fn counts(
+    app: &Router,
+    collection_id: &str,
+    card: (&str, &str),
+    format_id: &str,
+) -> Option<Vec<i64>> {
+    let (status, body) = send(
+        app,
+        Method::GET,
+        &format!("/ratings?collection_id={}", collection_id),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body = serde_json::from_slice::<Value>(&body).unwrap();
+
+    body["ratings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|x| x["set_code"] == card.0 && x["card_code"] == card.1)
+        .and_then(|x| x["rating_by_format"].get(format_id))
+        .map(|x| {
+            (1..=5)
+                .map(|i| x[format!("rated_{}", i)].as_i64().unwrap())
+                .collect()
+        })
+}
+
+#[tokio::test]
+async fn init_db_registers_fixture_cards() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    setup(&db.pool).await;
+
+    // Three cards in one format for `tst`, one card in both formats for `new`
+    let rows: Vec<(String, String, i64)> = sqlx::query_as(
+        "SELECT collection_id, format_id, count(*) FROM ratings GROUP BY 1, 2 ORDER BY 1, 2",
+    )
+    .fetch_all(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            ("new".into(), "cube".into(), 1),
+            ("new".into(), "limited".into(), 1),
+            ("tst".into(), "limited".into(), 3),
+        ]
+    );
+
+    let (rarity, colors): (String, Vec<String>) = sqlx::query_as(
+        "SELECT rarity, colors FROM cards WHERE set_code = 'tst' AND card_code = '3'",
+    )
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert_eq!(rarity, "rare");
+    assert_eq!(colors, vec!["W", "U"]);
+}
+
+#[tokio::test]
+async fn import_bulk_file_counts_stored_cards() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    migrations::migrate_up(&db.pool, None).await.unwrap();
+    let path = std::env::temp_dir().join(format!("bulk-{}.json", std::process::id()));
+    // A german printing, a duplicate and a set code too long to store next to two regular cards
+    std::fs::write(
+        &path,
+        r#"[
+            {"set": "blk", "collector_number": "1", "name": "One", "lang": "en"},
+            {"set": "blk", "collector_number": "1", "name": "Eins", "lang": "de"},
+            {"set": "blk", "collector_number": "1", "name": "One", "lang": "en"},
+            {"set": "blk", "collector_number": "2a", "name": "Two"},
+            {"set": "waytoolongsetcode", "collector_number": "3", "name": "Three"}
+        ]"#,
+    )
+    .unwrap();
+
+    let imported = backend::bulk::import_bulk_file(&db.pool, &path).await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(imported.unwrap(), 2);
+}
+
+// Waits for the collections file watcher, which polls every few seconds
+async fn until<F: std::future::Future<Output = bool>>(mut f: impl FnMut() -> F) -> bool {
+    for _ in 0..40 {
+        if f().await {
+            return true;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn collections_file_retries_failed_registrations() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let dir = std::env::temp_dir().join(format!("collections-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // No fixture for the new collection yet, so registering its cards fails
+    let state = AppState {
+        card_source: Arc::new(FixtureSource(dir.clone())),
+        ..state
+    };
+    let app = client(&state, [10, 8, 0, 1]);
+    let path = dir.join("collections.json");
+    let mut collections = catalogue();
+    collections.entries.insert(
+        "xtr".into(),
+        Collection {
+            title: "XTR".into(),
+            scryfall_query: "set%3Axtr".into(),
+            set_order: vec!["xtr".into()],
+            ..Collection::default()
+        },
+    );
+    std::fs::write(&path, serde_json::to_vec(&collections).unwrap()).unwrap();
+    backend::reload::watch_collections_file(state.clone(), path, vec![]);
+
+    let live = || async {
+        let (_, body) = send(&app, Method::GET, "/collections").await;
+        serde_json::from_slice::<Value>(&body).unwrap()["entries"]["xtr"].is_object()
+    };
+    tokio::time::sleep(std::time::Duration::from_secs(6)).await;
+    assert!(!live().await);
+    // Admin writes reload the catalogue from the database, which must not have moved on either
+    assert!(!collections::load_collections(&db.pool)
+        .await
+        .unwrap()
+        .entries
+        .contains_key("xtr"));
+
+    std::fs::write(
+        dir.join("xtr.json"),
+        r#"[{"set": "xtr", "collector_number": "1", "name": "Late Card"}]"#,
+    )
+    .unwrap();
+    assert!(until(live).await);
+    assert_eq!(
+        counts(&app, "xtr", ("xtr", "1"), "limited").await,
+        Some(vec![0, 0, 0, 0, 0])
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn post_ratings_counts_and_records_votes() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 0, 0, 1]);
+
+    assert_eq!(
+        rate(&app, "tst", ("tst", "1"), "limited", 5).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        rate(
+            &client(&state, [10, 0, 0, 2]),
+            "tst",
+            ("tst", "1"),
+            "limited",
+            2
+        )
+        .await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 1, 0, 0, 1])
+    );
+
+    // Votes are stored under a keyed hash of the address, never the address itself
+    let voters: Vec<(String,)> = sqlx::query_as("SELECT voter_key FROM votes ORDER BY id")
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(voters.len(), 2);
+    assert_ne!(voters[0], voters[1]);
+    assert!(voters
+        .iter()
+        .all(|(x,)| x.len() == 64 && !x.contains("10.0.0")));
+
+    // The counters can be rebuilt from the recorded votes
+    sqlx::query("UPDATE ratings SET rated_5 = 0")
+        .execute(&db.pool)
+        .await
+        .unwrap();
+    lib::rebuild_ratings(&db.pool, Some("tst")).await.unwrap();
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 1, 0, 0, 1])
+    );
+}
+
+#[tokio::test]
+async fn snapshot_job_snapshots_every_period() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 9, 0, 1]);
+    assert_eq!(
+        rate(&app, "tst", ("tst", "1"), "limited", 4).await,
+        StatusCode::OK
+    );
+    let snapshots = || async {
+        sqlx::query_scalar::<_, i64>("SELECT count(DISTINCT taken_at) FROM ratings_snapshots")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    };
+
+    // Right after a snapshot, a restarted job waits for the rest of the period
+    snapshots::take_snapshot(&db.pool).await.unwrap();
+    snapshots::spawn_snapshot_job(db.pool.clone(), std::time::Duration::from_secs(60));
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert_eq!(snapshots().await, 1);
+
+    // Then every period, here at 1, 2 and 3 seconds after the first one
+    snapshots::spawn_snapshot_job(db.pool.clone(), std::time::Duration::from_secs(1));
+    tokio::time::sleep(std::time::Duration::from_millis(3200)).await;
+    assert_eq!(snapshots().await, 4);
+}
+
+#[tokio::test]
+async fn voter_tokens_change_and_retract_votes() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 0, 0, 1]);
+    let uri = |rating: u8| {
+        format!(
+            "/ratings?collection_id=tst&set_code=tst&card_code=1&format_id=limited&rating={}",
+            rating
+        )
+    };
+    let retract = "/ratings?collection_id=tst&set_code=tst&card_code=1&format_id=limited";
+
+    // The first vote hands out a token as a cookie and as a header
+    let (status, headers, _) = send_with_headers(&app, Method::POST, &uri(1), &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = headers["x-voter-token"].to_str().unwrap().to_owned();
+    let cookie = headers[header::SET_COOKIE].to_str().unwrap();
+    assert!(cookie.starts_with(&format!("voter_token={};", token)));
+    assert!(cookie.contains("HttpOnly"));
+
+    // Voting again with it moves the vote, the cookie works just like the header
+    let (status, headers, _) = send_with_headers(
+        &app,
+        Method::POST,
+        &uri(5),
+        &[("x-voter-token", token.as_str())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!headers.contains_key(header::SET_COOKIE));
+    let with_cookie = format!("theme=dark; voter_token={}", token);
+    let (status, _, _) = send_with_headers(
+        &app,
+        Method::POST,
+        &uri(4),
+        &[("cookie", with_cookie.as_str())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 0, 0, 1, 0])
+    );
+
+    // Forged tokens count as a new voter
+    let forged = format!("{}.{}", &token[..32], "0".repeat(64));
+    let (status, headers, _) = send_with_headers(
+        &client(&state, [10, 0, 0, 2]),
+        Method::POST,
+        &uri(2),
+        &[("x-voter-token", forged.as_str())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(headers["x-voter-token"], token.as_str());
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 1, 0, 1, 0])
+    );
+
+    let (status, _, _) = send_with_headers(
+        &app,
+        Method::DELETE,
+        retract,
+        &[("x-voter-token", token.as_str())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 1, 0, 0, 0])
+    );
+    let (status, _, body) = send_with_headers(
+        &app,
+        Method::DELETE,
+        retract,
+        &[("x-voter-token", token.as_str())],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        serde_json::from_slice::<Value>(&body).unwrap()["code"],
+        "vote_not_found"
+    );
+    let (status, _) = send(&app, Method::DELETE, retract).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The recorded votes agree with the counters
+    lib::rebuild_ratings(&db.pool, Some("tst")).await.unwrap();
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 1, 0, 0, 0])
+    );
+}
+
+#[tokio::test]
+async fn anomalous_votes_are_quarantined_until_reviewed() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 0, 0, 1]);
+    let thresholds = AnomalyThresholds {
+        window_secs: 3600.0,
+        subnet_votes: 5,
+        burst_votes: 5,
+        burst_secs: 10.0,
+    };
+
+    // Six fives from one /24 next to a regular vote, then five ones from as many subnets at once
+    for i in 1..=6 {
+        assert_eq!(
+            rate(
+                &client(&state, [10, 0, 0, i]),
+                "tst",
+                ("tst", "1"),
+                "limited",
+                5
+            )
+            .await,
+            StatusCode::OK
+        );
+    }
+    assert_eq!(
+        rate(
+            &client(&state, [10, 0, 99, 1]),
+            "tst",
+            ("tst", "1"),
+            "limited",
+            3
+        )
+        .await,
+        StatusCode::OK
+    );
+    for i in 1..=5 {
+        assert_eq!(
+            rate(
+                &client(&state, [10, 1, i, 1]),
+                "tst",
+                ("tst", "2"),
+                "limited",
+                1
+            )
+            .await,
+            StatusCode::OK
+        );
+    }
+
+    assert_eq!(
+        quarantine::quarantine_anomalies(&db.pool, &thresholds)
+            .await
+            .unwrap(),
+        11
+    );
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 0, 1, 0, 0])
+    );
+    assert_eq!(
+        counts(&app, "tst", ("tst", "2"), "limited").await,
+        Some(vec![0, 0, 0, 0, 0])
+    );
+    lib::rebuild_ratings(&db.pool, Some("tst")).await.unwrap();
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 0, 1, 0, 0])
+    );
+
+    let (status, groups) = admin(
+        &app,
+        Method::GET,
+        "/admin/quarantine?collection_id=tst",
+        Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let groups = groups
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| {
+            (
+                x["card_code"].as_str().unwrap(),
+                x["reason"].as_str().unwrap(),
+                x["votes"].as_i64().unwrap(),
+                x["subnets"].as_i64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        groups,
+        vec![("1", "subnet_burst", 6, 1), ("2", "time_burst", 5, 5)]
+    );
+
+    // Accepted votes count again and are not flagged a second time
+    let (status, body) = admin(
+        &app,
+        Method::POST,
+        "/admin/quarantine/accept",
+        serde_json::json!({"collection_id": "tst", "set_code": "tst", "card_code": "1"}),
+    )
+    .await;
+    assert_eq!((status, body["votes"].as_i64()), (StatusCode::OK, Some(6)));
+    assert_eq!(
+        quarantine::quarantine_anomalies(&db.pool, &thresholds)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 0, 1, 0, 6])
+    );
+
+    // Purged votes are gone for good
+    let (status, body) = admin(
+        &app,
+        Method::POST,
+        "/admin/quarantine/purge",
+        serde_json::json!({"collection_id": "tst"}),
+    )
+    .await;
+    assert_eq!((status, body["votes"].as_i64()), (StatusCode::OK, Some(5)));
+    lib::rebuild_ratings(&db.pool, Some("tst")).await.unwrap();
+    assert_eq!(
+        counts(&app, "tst", ("tst", "2"), "limited").await,
+        Some(vec![0, 0, 0, 0, 0])
+    );
+    let (_, groups) = admin(&app, Method::GET, "/admin/quarantine", Value::Null).await;
+    assert_eq!(groups, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn blocked_clients_cannot_vote() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 2, 3, 4]);
+    let vote = |app: Router| async move { rate(&app, "tst", ("tst", "1"), "limited", 4).await };
+
+    let (status, body) = admin(
+        &app,
+        Method::POST,
+        "/admin/blocked",
+        serde_json::json!({"network": "10.2.9.9/16", "reason": "spam"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["network"], "10.2.0.0/16");
+    let id = body["id"].as_i64().unwrap();
+
+    let (status, _, body) = send_with_headers(
+        &app,
+        Method::POST,
+        "/ratings?collection_id=tst&set_code=tst&card_code=1&format_id=limited&rating=4",
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        serde_json::from_slice::<Value>(&body).unwrap()["code"],
+        "blocked"
+    );
+    assert_eq!(vote(client(&state, [10, 3, 0, 1])).await, StatusCode::OK);
+    // Reads stay open to blocked clients
+    assert_eq!(
+        send(&app, Method::GET, "/ratings?collection_id=tst")
+            .await
+            .0,
+        StatusCode::OK
+    );
+
+    // Expired entries are listed but don't block, bad networks are rejected
+    let (status, _) = admin(
+        &app,
+        Method::POST,
+        "/admin/blocked",
+        serde_json::json!({"network": "10.3.0.1", "expires_at": "2000-01-01T00:00:00Z"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(vote(client(&state, [10, 3, 0, 1])).await, StatusCode::OK);
+    let (status, body) = admin(
+        &app,
+        Method::POST,
+        "/admin/blocked",
+        serde_json::json!({"network": "10.3.0.0/33"}),
+    )
+    .await;
+    assert_eq!(
+        (status, body["code"].as_str()),
+        (StatusCode::BAD_REQUEST, Some("invalid_request"))
+    );
+    let (status, body) = admin(&app, Method::GET, "/admin/blocked", Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().map(Vec::len), Some(2));
+
+    // Entries added by another replica show up on the next refresh
+    sqlx::query("INSERT INTO blocked_clients (network) VALUES ('10.4.0.7/32')")
+        .execute(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(vote(client(&state, [10, 4, 0, 7])).await, StatusCode::OK);
+    backend::blocklist::reload(&state).await.unwrap();
+    assert_eq!(
+        vote(client(&state, [10, 4, 0, 7])).await,
+        StatusCode::FORBIDDEN
+    );
+
+    let uri = format!("/admin/blocked/{}", id);
+    assert_eq!(
+        admin(&app, Method::DELETE, &uri, Value::Null).await.0,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(vote(app.clone()).await, StatusCode::OK);
+    let (status, body) = admin(&app, Method::DELETE, &uri, Value::Null).await;
+    assert_eq!(
+        (status, body["code"].as_str()),
+        (StatusCode::NOT_FOUND, Some("block_not_found"))
+    );
+}
+
+// The first solution with at least `difficulty` leading zero bits
+fn solve(challenge: &str, difficulty: u32) -> String {
+    (0..)
+        .map(|x: u32| x.to_string())
+        .find(|x| pow::solution_bits(challenge, x) >= difficulty)
+        .unwrap()
+}
+
+async fn challenge(app: &Router, collection_id: &str) -> (String, u64) {
+    let (status, body) = send(
+        app,
+        Method::GET,
+        &format!("/challenge?collection_id={}", collection_id),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body = serde_json::from_slice::<Value>(&body).unwrap();
+
+    (
+        body["challenge"].as_str().unwrap().to_owned(),
+        body["difficulty"].as_u64().unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn votes_need_a_solved_challenge() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 5, 0, 1]);
+    let vote = |challenge: &str, solution: &str| {
+        format!(
+            "/ratings?collection_id=tst&set_code=tst&card_code=1&format_id=limited&rating=4&challenge={}&solution={}",
+            challenge, solution
+        )
+    };
+    let code = |body: &[u8]| serde_json::from_slice::<Value>(body).unwrap()["code"].clone();
+
+    let (status, body) = admin(
+        &app,
+        Method::PATCH,
+        "/admin/collections/tst",
+        serde_json::json!({"pow_difficulty": 8}),
+    )
+    .await;
+    assert_eq!(
+        (status, body["pow_difficulty"].as_u64()),
+        (StatusCode::OK, Some(8))
+    );
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/ratings?collection_id=tst&set_code=tst&card_code=1&format_id=limited&rating=4",
+    )
+    .await;
+    let body = serde_json::from_slice::<Value>(&body).unwrap();
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        (&body["code"], &body["details"]["difficulty"]),
+        (&"challenge_required".into(), &8.into())
+    );
+
+    let (x, difficulty) = challenge(&app, "tst").await;
+    assert_eq!(difficulty, 8);
+    let wrong = (0..)
+        .map(|x: u32| x.to_string())
+        .find(|s| pow::solution_bits(&x, s) < 8)
+        .unwrap();
+    let (status, body) = send(&app, Method::POST, &vote(&x, &wrong)).await;
+    assert_eq!(
+        (status, code(&body)),
+        (StatusCode::FORBIDDEN, "invalid_challenge".into())
+    );
+    let solution = solve(&x, 8);
+    // Votes on cards outside the collection are dropped without spending the challenge
+    let unknown = vote(&x, &solution).replace("card_code=1&", "card_code=9&");
+    assert_eq!(send(&app, Method::POST, &unknown).await.0, StatusCode::OK);
+    assert_eq!(
+        send(&app, Method::POST, &vote(&x, &solution)).await.0,
+        StatusCode::OK
+    );
+    // Challenges are single use
+    let (status, body) = send(&app, Method::POST, &vote(&x, &solution)).await;
+    assert_eq!(
+        (status, code(&body)),
+        (StatusCode::FORBIDDEN, "invalid_challenge".into())
+    );
+    assert_eq!(
+        counts(&app, "tst", ("tst", "1"), "limited").await,
+        Some(vec![0, 0, 0, 1, 0])
+    );
+
+    // Challenges only count for the collection they were issued for, which may need none
+    let (x, difficulty) = challenge(&app, "new").await;
+    assert_eq!(difficulty, 0);
+    let (status, body) = send(&app, Method::POST, &vote(&x, &solve(&x, 8))).await;
+    assert_eq!(
+        (status, code(&body)),
+        (StatusCode::FORBIDDEN, "invalid_challenge".into())
+    );
+    assert_eq!(
+        rate(&app, "new", ("new", "1"), "limited", 3).await,
+        StatusCode::OK
+    );
+
+    // Denied votes raise the difficulty everywhere
+    let state = AppState {
+        pow: PowSettings {
+            pressure_denials: Some(1),
+            ..PowSettings::default()
+        },
+        rate_limiter: Arc::new(RateLimiter::new(
+            Arc::new(MemoryStore::default()),
+            RateLimits {
+                rate_limit_votes_per_ip: Limit::new(1, 3600),
+                ..RateLimits::default()
+            },
+        )),
+        ..state.clone()
+    };
+    let app = client(&state, [10, 6, 0, 1]);
+    assert_eq!(
+        rate(&app, "new", ("new", "1"), "limited", 3).await,
+        StatusCode::OK
+    );
+    let (easy, difficulty) = challenge(&app, "new").await;
+    assert_eq!(difficulty, 0);
+    let (before, difficulty) = challenge(&app, "tst").await;
+    assert_eq!(difficulty, 8);
+    let (stale, _) = challenge(&app, "tst").await;
+    assert_eq!(
+        rate(&app, "new", ("new", "1"), "limited", 3).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(challenge(&app, "new").await.1, 1);
+    assert_eq!(challenge(&app, "tst").await.1, 9);
+    assert_eq!(
+        rate(
+            &client(&state, [10, 7, 0, 1]),
+            "new",
+            ("new", "1"),
+            "limited",
+            3
+        )
+        .await,
+        StatusCode::FORBIDDEN
+    );
+
+    // Easy challenges collected before don't get around the raise, slightly older ones still count
+    let (status, body) = send(
+        &client(&state, [10, 7, 0, 2]),
+        Method::POST,
+        &format!(
+            "/ratings?collection_id=new&set_code=new&card_code=1&format_id=limited&rating=3&challenge={}&solution=0",
+            easy
+        ),
+    )
+    .await;
+    assert_eq!(
+        (status, code(&body)),
+        (StatusCode::FORBIDDEN, "invalid_challenge".into())
+    );
+    assert_eq!(
+        send(
+            &client(&state, [10, 7, 0, 3]),
+            Method::POST,
+            &vote(&before, &solve(&before, 8))
+        )
+        .await
+        .0,
+        StatusCode::OK
+    );
+
+    // Neither do challenges below the collection's own difficulty
+    let (status, _) = admin(
+        &app,
+        Method::PATCH,
+        "/admin/collections/tst",
+        serde_json::json!({"pow_difficulty": 10}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(
+        &client(&state, [10, 7, 0, 4]),
+        Method::POST,
+        &vote(&stale, &solve(&stale, 8)),
+    )
+    .await;
+    assert_eq!(
+        (status, code(&body)),
+        (StatusCode::FORBIDDEN, "invalid_challenge".into())
+    );
+}
+
+#[tokio::test]
+async fn post_ratings_rejects_bad_input() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 0, 0, 1]);
+
+    assert_eq!(
+        rate(&app, "tst", ("tst", "1"), "vintage", 3).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        rate(&app, "tst", ("tst", "2"), "cube", 3).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        rate(&app, "tst", ("tst", "3"), "limited", 6).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        rate(&app, "nope", ("tst", "1"), "limited", 3).await,
+        StatusCode::BAD_REQUEST
+    );
+
+    let (status, body) = send(&app, Method::GET, "/ratings?collection_id=nope").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body = serde_json::from_slice::<Value>(&body).unwrap();
+    assert_eq!(body["code"], "unknown_collection");
+    assert_eq!(body["details"]["collection_id"], "nope");
+    assert_eq!(counts(&app, "tst", ("tst", "2"), "cube").await, None);
+
+    let (status, body) = send(&app, Method::GET, "/cards/tst/999").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        serde_json::from_slice::<Value>(&body).unwrap()["code"],
+        "card_not_found"
+    );
+}
+
+#[tokio::test]
+async fn malformed_keys_are_rejected_as_json() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 0, 0, 1]);
+
+    for uri in [
+        "/ratings?collection_id=tst&set_code=tst&card_code=12345678901234567&format_id=limited&rating=3",
+        "/ratings?collection_id=tst&set_code=ts%27t&card_code=1&format_id=limited&rating=3",
+        "/ratings?collection_id=tst&set_code=tst&card_code=1&format_id=limited",
+    ] {
+        let (status, body) = send(&app, Method::POST, uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        let body = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(body["code"], "invalid_request");
+        assert!(body["message"].as_str().is_some_and(|x| !x.is_empty()));
+    }
+
+    let (status, _) = send(&app, Method::GET, "/cards/tst/a%20b").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn post_ratings_limits_repeated_votes() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let mut state = setup(&db.pool).await;
+    let limits = RateLimits {
+        rate_limit_votes_per_card: Limit::new(2, 3600),
+        ..Default::default()
+    };
+    state.rate_limiter = Arc::new(RateLimiter::new(
+        Arc::new(PostgresStore(db.pool.clone())),
+        limits,
+    ));
+    let app = client(&state, [10, 0, 0, 1]);
+    let uri = "/ratings?collection_id=tst&set_code=tst&card_code=2&format_id=limited&rating=4";
+
+    let (status, headers, _) = send_with_headers(&app, Method::POST, uri, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["ratelimit-limit"], "2");
+    assert_eq!(headers["ratelimit-remaining"], "1");
+    assert_eq!(
+        rate(&app, "tst", ("tst", "2"), "limited", 4).await,
+        StatusCode::OK
+    );
+    let (status, headers, body) = send_with_headers(&app, Method::POST, uri, &[]).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        serde_json::from_slice::<Value>(&body).unwrap()["code"],
+        "rate_limited"
+    );
+    assert!(headers[header::RETRY_AFTER]
+        .to_str()
+        .unwrap()
+        .parse::<u64>()
+        .is_ok_and(|x| x > 0 && x <= 1800));
+
+    // Buckets live in the database, a restarted or second backend sees the same limits
+    let restarted = AppState {
+        rate_limiter: Arc::new(RateLimiter::new(
+            Arc::new(PostgresStore(db.pool.clone())),
+            limits,
+        )),
+        ..state.clone()
+    };
+    assert_eq!(
+        rate(
+            &client(&restarted, [10, 0, 0, 1]),
+            "tst",
+            ("tst", "2"),
+            "limited",
+            4
+        )
+        .await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Other clients and other cards have their own buckets
+    assert_eq!(
+        rate(
+            &client(&state, [10, 0, 0, 2]),
+            "tst",
+            ("tst", "2"),
+            "limited",
+            4
+        )
+        .await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        rate(&app, "tst", ("tst", "3"), "limited", 4).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        counts(&app, "tst", ("tst", "2"), "limited").await,
+        Some(vec![0, 0, 0, 3, 0])
+    );
+
+    // Reads are limited separately
+    let (status, headers, _) =
+        send_with_headers(&app, Method::GET, "/ratings?collection_id=tst", &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["ratelimit-limit"], "600");
+}
+
+#[tokio::test]
+async fn post_ratings_adds_cards_of_releasing_sets() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    let state = setup(&db.pool).await;
+    let app = client(&state, [10, 0, 0, 1]);
+
+    // Spoiled after the collection was resolved
+    assert_eq!(
+        rate(&app, "new", ("new", "7"), "limited", 5).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        counts(&app, "new", ("new", "7"), "limited").await,
+        Some(vec![0, 0, 0, 0, 1])
+    );
+    assert_eq!(
+        counts(&app, "new", ("new", "7"), "cube").await,
+        Some(vec![0, 0, 0, 0, 0])
+    );
+
+    // Collector numbers past the main set, sets outside the collection and finished sets are left alone
+    assert_eq!(
+        rate(&app, "new", ("new", "1234"), "limited", 5).await,
+        StatusCode::OK
+    );
+    assert_eq!(counts(&app, "new", ("new", "1234"), "limited").await, None);
+    assert_eq!(
+        rate(&app, "new", ("tst", "8"), "limited", 5).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        rate(&app, "tst", ("tst", "9"), "limited", 5).await,
+        StatusCode::OK
+    );
+    assert_eq!(counts(&app, "tst", ("tst", "9"), "limited").await, None);
+}
+
+#[tokio::test]
+async fn ratings_queries_bind_their_values() {
+    let Some(db) = TestDb::start().await else {
+        return;
+    };
+    setup(&db.pool).await;
+
+    let card = |set: &str, collector_number: &str| CardDetail {
+        set: set.into(),
+        collector_number: collector_number.into(),
+        ..Default::default()
+    };
+    init_db::run_ratings_query(
+        &db.pool,
+        &[Format {
+            title: "limited".into(),
+            enabled: true,
+        }],
+        &("tst".into(), vec![card("o'k", "1'); --"), card("zzz", "1")]),
+    )
+    .await
+    .unwrap();
+
+    let order = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    let ratings = lib::get_ratings(
+        &db.pool,
+        "tst",
+        &order(&["zzz", "o'k"]),
+        &RatingsFilter::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        ratings
+            .iter()
+            .map(|x| (x.set_code.as_str(), x.card_code.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("zzz", "1"),
+            ("o'k", "1'); --"),
+            ("tst", "1"),
+            ("tst", "2"),
+            ("tst", "3"),
+        ]
+    );
+
+    let limited = lib::get_ratings(
+        &db.pool,
+        "tst",
+        &order(&["tst"]),
+        &RatingsFilter {
+            format_id: Some("limited".into()),
+            ..RatingsFilter::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(!limited.is_empty());
+    assert!(limited.iter().all(|x| x.format_id == "limited"));
+}
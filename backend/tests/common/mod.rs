@@ -0,0 +1,143 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    ConnectOptions, Connection, Executor, PgConnection, PgPool,
+};
+
+static DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+enum Server {
+    // A cluster created with `initdb` for this test only, listening on a socket in its data directory
+    Cluster { dir: PathBuf },
+    // A database created on the server of `TEST_DATABASE_URL`
+    Database { url: String, name: String },
+}
+
+// A throwaway postgres database, removed again when dropped
+pub struct TestDb {
+    pub pool: PgPool,
+    server: Server,
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    match command.output() {
+        Ok(x) if x.status.success() => Ok(()),
+        Ok(x) => Err(String::from_utf8_lossy(&x.stderr).into_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn start_cluster(dir: &Path) -> Result<PgConnectOptions, String> {
+    run(Command::new("initdb").arg("-D").arg(dir).args([
+        "-U",
+        "postgres",
+        "--auth=trust",
+        "-E",
+        "UTF8",
+        "--no-sync",
+    ]))?;
+    run(Command::new("pg_ctl")
+        .arg("-D")
+        .arg(dir)
+        .arg("-l")
+        .arg(dir.join("server.log"))
+        .arg("-o")
+        .arg(format!(
+            "-c listen_addresses='' -c unix_socket_directories='{}' -c fsync=off",
+            dir.display()
+        ))
+        .args(["-w", "start"]))?;
+
+    Ok(PgConnectOptions::new()
+        .socket(dir)
+        .username("postgres")
+        .database("postgres"))
+}
+
+impl TestDb {
+    // Fails the test if neither `TEST_DATABASE_URL` is set nor a local cluster can be started, e.g. when running as root.
+    // `None` only with `SKIP_DB_TESTS=1`, for machines that can't run them at all
+    pub async fn start() -> Option<TestDb> {
+        let id = format!(
+            "backend_test_{}_{}",
+            std::process::id(),
+            DATABASES.fetch_add(1, Ordering::SeqCst)
+        );
+
+        let (options, server) = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => {
+                let mut conn = PgConnection::connect(&url).await.unwrap();
+                conn.execute(format!("CREATE DATABASE {}", id).as_str())
+                    .await
+                    .unwrap();
+                conn.close().await.unwrap();
+                let options = url.parse::<PgConnectOptions>().unwrap().database(&id);
+                (options, Server::Database { url, name: id })
+            }
+            Err(_) => {
+                let dir = std::env::temp_dir().join(&id);
+                match start_cluster(&dir) {
+                    Ok(options) => (options, Server::Cluster { dir }),
+                    Err(e) => {
+                        let _ = std::fs::remove_dir_all(&dir);
+                        if std::env::var("SKIP_DB_TESTS").as_deref() == Ok("1") {
+                            eprintln!("Skipping database test, SKIP_DB_TESTS=1 is set");
+                            return None;
+                        }
+                        panic!(
+                            "No test database, set TEST_DATABASE_URL, make initdb available or set SKIP_DB_TESTS=1: {}",
+                            e.trim()
+                        );
+                    }
+                }
+            }
+        };
+
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options.disable_statement_logging())
+            .await
+            .unwrap();
+        Some(TestDb { pool, server })
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        match &self.server {
+            Server::Cluster { dir } => {
+                let _ = run(Command::new("pg_ctl").arg("-D").arg(dir).args([
+                    "-m",
+                    "immediate",
+                    "stop",
+                ]));
+                let _ = std::fs::remove_dir_all(dir);
+            }
+            // Dropping needs a connection of its own, the test's runtime may already be gone
+            Server::Database { url, name } => {
+                let (url, name) = (url.clone(), name.clone());
+                std::thread::spawn(move || {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .unwrap()
+                        .block_on(async {
+                            let mut conn = PgConnection::connect(&url).await.unwrap();
+                            conn.execute(
+                                format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", name).as_str(),
+                            )
+                            .await
+                            .unwrap();
+                        })
+                })
+                .join()
+                .unwrap();
+            }
+        }
+    }
+}
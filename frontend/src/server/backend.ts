@@ -198,8 +198,44 @@ function updateFromCollectionInfo(ratings: Ratings, collectionInfo: CollectionIn
     }
 }
 
+type ChallengeJson = {
+    challenge: string,
+    difficulty: number,
+    expires_at: string,
+}
+
+type SolvedChallenge = {
+    challenge: string,
+    solution: string,
+    expiresAt: number,
+}
+
+function leadingZeroBits(hash: Uint8Array): number {
+    let bits = 0;
+    for (const x of hash) {
+        if (x !== 0) {
+            return bits + Math.clz32(x) - 24;
+        }
+        bits += 8;
+    }
+    return bits;
+}
+
+// Any solution for which SHA-256 of `<challenge>:<solution>` starts with `difficulty` zero bits, like the backend checks it
+async function solveChallenge(challenge: string, difficulty: number): Promise<string> {
+    const encoder = new TextEncoder();
+    for (let i = 0; ; i++) {
+        const solution = i.toString();
+        const hash = await crypto.subtle.digest("SHA-256", encoder.encode(`${challenge}:${solution}`));
+        if (leadingZeroBits(new Uint8Array(hash)) >= difficulty) {
+            return solution;
+        }
+    }
+}
+
 export default class Backend {
     private server_url: string = "not set";
+    private solvedChallenges: Map<string, Promise<SolvedChallenge | null>> = new Map();
 
     public constructor(url: string) {
         this.server_url = url;
@@ -223,9 +259,50 @@ export default class Backend {
 
     public postRating({ collectionId, formatId, rating, cardCode, setCode }: RatingsPostRequest): void {
         setLocalStorageRating(collectionId, formatId, setCode, cardCode, rating);
-        fetch(`${this.server_url}/ratings?collection_id=${collectionId}&rating=${rating}&card_code=${cardCode}&set_code=${setCode}&format_id=${formatId}`, {
-            method: "POST",
-        });
+        this.postWithProofOfWork(
+            collectionId,
+            `${this.server_url}/ratings?collection_id=${collectionId}&rating=${rating}&card_code=${cardCode}&set_code=${setCode}&format_id=${formatId}`,
+        );
+    }
+
+    // Collections only need a proof of work some of the time, so it's only solved once the backend asks for it.
+    // After that the next challenge is solved ahead and sent with the first request of the next vote.
+    private async postWithProofOfWork(collectionId: string, url: string): Promise<void> {
+        // Taken out right away, so concurrent votes never send the same challenge
+        const cached = this.solvedChallenges.get(collectionId);
+        this.solvedChallenges.delete(collectionId);
+        const withSolution = (x: SolvedChallenge | null | undefined) =>
+            x && x.expiresAt > Date.now() ? `${url}&challenge=${encodeURIComponent(x.challenge)}&solution=${x.solution}` : url;
+
+        let solved = await cached;
+        const response = await fetch(withSolution(solved), { method: "POST" });
+        if (response.status === 403) {
+            const error = await response.json() as { code: string };
+            if (error.code !== "challenge_required" && error.code !== "invalid_challenge") {
+                return;
+            }
+            solved = await this.solveChallengeFor(collectionId);
+            if (solved === null) {
+                return;
+            }
+            await fetch(withSolution(solved), { method: "POST" });
+        }
+        if (solved) {
+            this.solvedChallenges.set(collectionId, this.solveChallengeFor(collectionId).catch(() => null));
+        }
+    }
+
+    // A solved challenge for the next vote in the collection, null if it needs none right now
+    private async solveChallengeFor(collectionId: string): Promise<SolvedChallenge | null> {
+        const response = await fetch(`${this.server_url}/challenge?collection_id=${collectionId}`);
+        if (!response.ok) {
+            return null;
+        }
+        const { challenge, difficulty, expires_at } = await response.json() as ChallengeJson;
+        if (difficulty === 0) {
+            return null;
+        }
+        return { challenge, solution: await solveChallenge(challenge, difficulty), expiresAt: Date.parse(expires_at) };
     }
 
     public async getCollectionMetadata(): Promise<Collections> {